@@ -0,0 +1,87 @@
+use std::{collections::HashMap, ops::Deref};
+
+use crate::{control::driver::{Driver, Stop}, utils::{id::TrainId, io::YamlTrain}};
+
+pub const DEFAULT_LOOK_AHEAD: usize = 2;
+
+// the order the signaller gives trains the road in, and how many signals beyond the one each train is approaching
+// it sets routes for ahead of it, empty and none when the signalling is left to react to requests
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Regulation {
+    pub order: Vec<TrainId>,
+    pub look_ahead: usize,
+}
+
+// automatic route setting - works out from the timetables which trains should go first, so that where two want the
+// same junction the one with the higher priority goes first, and the later running of two alike
+pub struct Ars {
+    look_ahead: usize,
+    priorities: HashMap<TrainId, u32>,
+}
+
+impl Ars {
+    pub fn new(look_ahead: usize, trains: &[YamlTrain]) -> Self {
+        Ars {
+            look_ahead,
+            priorities: trains.iter().map(|train| (TrainId::new(&train.id), train.priority)).collect(),
+        }
+    }
+
+    // every train, by priority then lateness then name so the order is the same every run
+    pub fn regulate(&self, time: f32, drivers: &[impl Deref<Target = Driver>]) -> Regulation {
+        let mut trains: Vec<(u32, f32, TrainId)> = drivers.iter().map(|driver| {
+            let train_id = driver.train.name;
+            (self.priorities.get(&train_id).copied().unwrap_or(0), lateness(time, &driver.timetable), train_id)
+        }).collect();
+        trains.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.total_cmp(&a.1)).then(a.2.cmp(&b.2)));
+
+        Regulation {
+            order: trains.into_iter().map(|(_, _, train_id)| train_id).collect(),
+            look_ahead: self.look_ahead,
+        }
+    }
+}
+
+// seconds behind the timetable, by the last time achieved against it or by how long overdue the train is at its
+// next stop if that is later, and early as negative
+pub fn lateness(time: f32, timetable: &[Stop]) -> f32 {
+    let mut lateness = 0.0;
+    for stop in timetable {
+        let booked_arrival = stop.arrival.unwrap_or(stop.departure) as f32;
+        match (stop.arrived, stop.departed) {
+            (_, Some(departed)) => lateness = departed - stop.departure as f32,
+            (Some(arrived), None) => return (arrived - booked_arrival).max(time - stop.departure as f32),
+            (None, None) => return lateness.max(time - booked_arrival),
+        }
+    }
+    lateness
+}
+
+#[test]
+fn test_regulate() {
+    let mut timetable = vec![Stop::new("A".into(), 1, Some(100), 130, 30), Stop::new("B".into(), 1, Some(300), 300, 0)];
+    assert_eq!(lateness(50.0, &timetable), 0.0);
+    assert_eq!(lateness(160.0, &timetable), 60.0); // still short of A
+    timetable[0].arrived = Some(110.0);
+    assert_eq!(lateness(120.0, &timetable), 10.0);
+    timetable[0].departed = Some(140.0);
+    assert_eq!(lateness(200.0, &timetable), 10.0);
+    assert_eq!(lateness(340.0, &timetable), 40.0); // overdue at B
+
+    let (scenario, _) = crate::utils::io::passing_loop();
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut drivers: Vec<Box<Driver>> = scenario.trains.iter().map(|train| {
+        let timetable = train.timetable.iter().map(|stop| Stop::new(stop.location.as_str().into(), stop.platform, stop.arrival, stop.time, stop.dwell)).collect();
+        let class = crate::utils::io::find_class(&scenario.rolling_stock, &train.class).unwrap();
+        Box::new(Driver::new(tx.clone(), crate::infrastructure::train::Train::new(train.id.as_str().into(), class), train.start.as_str().into(), 0.1, timetable, Vec::new()))
+    }).collect();
+
+    // alike trains go in name order until one is running later, and a higher priority goes first whatever
+    let mut trains = scenario.trains.clone();
+    assert_eq!(Ars::new(2, &trains).regulate(0.0, &drivers).order, vec!["1A01", "2B02"]);
+    drivers[0].timetable[0].arrived = Some(590.0);
+    drivers[0].timetable[0].departed = Some(630.0);
+    assert_eq!(Ars::new(2, &trains).regulate(700.0, &drivers).order, vec!["2B02", "1A01"]); // 100s late against 30s
+    trains[0].priority = 1;
+    assert_eq!(Ars::new(2, &trains).regulate(700.0, &drivers).order, vec!["1A01", "2B02"]);
+}
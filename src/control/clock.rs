@@ -0,0 +1,103 @@
+use std::{panic::{self, AssertUnwindSafe}, sync::{Arc, Mutex}, thread};
+use tokio::{runtime::{Handle, Runtime}, sync::{mpsc, watch}};
+
+// anything that acts once a tick, each on a task of its own
+pub trait Agent: Send + 'static {
+    fn step(&mut self);
+}
+
+// each tick the signaller goes first, answering what the drivers said the tick before, then every driver at once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Signalling,
+    Driving,
+}
+
+const PHASES: usize = 2;
+
+// the simulated time the agents run to, rather than the wall clock. a phase only ends once every agent in it has
+// stepped, so however the tasks are scheduled each tick comes out the same
+pub struct Clock {
+    runtime: Runtime,
+    phases: [(watch::Sender<u32>, usize); PHASES], // each phase's tick count and how many agents step in it
+    done_tx: mpsc::UnboundedSender<thread::Result<()>>,
+    done: mpsc::UnboundedReceiver<thread::Result<()>>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        let (done_tx, done) = mpsc::unbounded_channel();
+
+        Clock {
+            runtime,
+            phases: [(watch::channel(0).0, 0), (watch::channel(0).0, 0)],
+            done_tx,
+            done,
+        }
+    }
+
+    // for anything else that wants to run alongside the agents, such as serving the dashboard
+    pub fn handle(&self) -> Handle {
+        self.runtime.handle().clone()
+    }
+
+    // the agent stays shared so the state of the run can be read between ticks
+    pub fn spawn<A: Agent>(&mut self, phase: Phase, agent: Arc<Mutex<A>>) {
+        let (tick, count) = &mut self.phases[phase as usize];
+        let mut tick = tick.subscribe();
+        *count += 1;
+        let done = self.done_tx.clone();
+
+        self.runtime.spawn(async move {
+            while tick.changed().await.is_ok() {
+                tick.borrow_and_update();
+                let stepped = panic::catch_unwind(AssertUnwindSafe(|| agent.lock().unwrap().step()));
+                if done.send(stepped).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // step every agent in the phase once, carrying on any panic as if it had happened here
+    pub fn run(&mut self, phase: Phase) {
+        let (tick, count) = &self.phases[phase as usize];
+        tick.send_modify(|tick| *tick += 1);
+        let done = &mut self.done;
+        let stepped = self.runtime.block_on(async {
+            for _ in 0..*count {
+                done.recv().await.unwrap()?;
+            }
+            Ok(())
+        });
+        if let Err(panic) = stepped {
+            panic::resume_unwind(panic);
+        }
+    }
+}
+
+#[test]
+fn test_phases() {
+    struct Logger(Arc<Mutex<Vec<Phase>>>, Phase);
+    impl Agent for Logger {
+        fn step(&mut self) {
+            self.0.lock().unwrap().push(self.1);
+        }
+    }
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut clock = Clock::new();
+    for phase in [Phase::Driving, Phase::Driving, Phase::Signalling, Phase::Driving] {
+        clock.spawn(phase, Arc::new(Mutex::new(Logger(log.clone(), phase))));
+    }
+    for _ in 0..100 {
+        clock.run(Phase::Signalling);
+        clock.run(Phase::Driving);
+    }
+
+    // every agent steps once a tick, and none of the drivers before the signaller
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 400);
+    assert!(log.chunks(4).all(|tick| tick[0] == Phase::Signalling && tick[1..].iter().all(|phase| *phase == Phase::Driving)));
+}
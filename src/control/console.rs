@@ -0,0 +1,100 @@
+use console::Key;
+use std::sync::mpsc::Receiver;
+
+use crate::utils::{id::BlockId, visualiser::keys};
+
+pub const HELP: &str = "route <from> <to> | cancel <block> | danger <block> | release <block> | space pause | . step | +/- speed";
+
+// signalling by hand, each naming the signal by the block it stands at the end of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Route { block_id: BlockId, next_block_id: BlockId }, // for the train approaching the signal
+    Cancel { block_id: BlockId }, // take a route back before the train has passed the signal, leaving it at danger
+    Danger { block_id: BlockId }, // put the signal back to danger and keep it there
+    Release { block_id: BlockId }, // let the signal clear again
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["route", block_id, next_block_id] => Ok(Command::Route { block_id: BlockId::new(block_id), next_block_id: BlockId::new(next_block_id) }),
+            ["cancel", block_id] => Ok(Command::Cancel { block_id: BlockId::new(block_id) }),
+            ["danger", block_id] => Ok(Command::Danger { block_id: BlockId::new(block_id) }),
+            ["release", block_id] => Ok(Command::Release { block_id: BlockId::new(block_id) }),
+            _ => Err(format!("unknown command '{line}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Pause,
+    Step, // one tick while paused
+    Faster,
+    Slower,
+    Edit, // the command being typed changed
+    Command(Command),
+}
+
+// keyboard input while the simulation runs, single keys work the clock and anything else is typed as a command
+pub struct Console {
+    keys: Receiver<Key>,
+    pub line: String,
+    pub message: String, // outcome of the last command
+}
+
+impl Console {
+    pub fn open() -> Self {
+        Console { keys: keys(), line: String::new(), message: HELP.to_string() }
+    }
+
+    pub fn poll(&mut self) -> Vec<Action> {
+        let keys: Vec<Key> = self.keys.try_iter().collect();
+        keys.into_iter().filter_map(|key| self.press(key)).collect()
+    }
+
+    fn press(&mut self, key: Key) -> Option<Action> {
+        match key {
+            Key::Char(' ') if self.line.is_empty() => Some(Action::Pause),
+            Key::Char('.') if self.line.is_empty() => Some(Action::Step),
+            Key::Char('+') | Key::Char('=') if self.line.is_empty() => Some(Action::Faster),
+            Key::Char('-') if self.line.is_empty() => Some(Action::Slower),
+            Key::Char(c) => {
+                self.line.push(c);
+                Some(Action::Edit)
+            },
+            Key::Backspace => {
+                self.line.pop();
+                Some(Action::Edit)
+            },
+            Key::Escape => {
+                self.line.clear();
+                Some(Action::Edit)
+            },
+            Key::Enter if !self.line.trim().is_empty() => {
+                let line = std::mem::take(&mut self.line);
+                match Command::parse(&line) {
+                    Ok(command) => Some(Action::Command(command)),
+                    Err(e) => {
+                        self.message = e;
+                        Some(Action::Edit)
+                    },
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub fn report(&mut self, result: Result<String, String>) {
+        self.message = result.unwrap_or_else(|e| format!("refused: {e}"));
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Command::parse("route E1  ME"), Ok(Command::Route { block_id: "E1".into(), next_block_id: "ME".into() }));
+    assert_eq!(Command::parse(" danger W"), Ok(Command::Danger { block_id: "W".into() }));
+    assert!(Command::parse("route E1").is_err());
+    assert!(Command::parse("reverse W").is_err());
+}
@@ -1,78 +1,224 @@
-use std::fmt::Display;
+use std::{collections::VecDeque, fmt::Display};
 
-use std::sync::mpsc::{SyncSender, Receiver, channel};
-use log::debug;
+use tokio::sync::mpsc::{UnboundedSender, UnboundedReceiver, unbounded_channel};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     infrastructure::{
         signal::SignalColour, train::Train
     },
-    control::message::{SignallerMessage, TrainMessage}
+    control::{clock::Agent, message::{SignallerMessage, TrainMessage}},
+    utils::{events::{Event, ThrottleState}, id::{BlockId, TrainId}}
 };
 
+// a timetabled call at a platform, with the times actually achieved once the train has been there
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stop {
+    pub location: BlockId,
+    pub platform: usize,
+    pub arrival: Option<u32>, // booked arrival, if the timetable gives one
+    pub departure: u32, // booked departure, or arrival at the last stop
+    pub dwell: u32, // least time to stand for once arrived
+    pub arrived: Option<f32>,
+    pub departed: Option<f32>,
+}
+
+impl Stop {
+    pub fn new(location: BlockId, platform: usize, arrival: Option<u32>, departure: u32, dwell: u32) -> Self {
+        Stop {
+            location,
+            platform,
+            arrival,
+            departure,
+            dwell,
+            arrived: None,
+            departed: None,
+        }
+    }
+}
+
+impl Display for Stop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let actual = |time: Option<f32>, booked: Option<u32>| match (time, booked) {
+            (Some(time), Some(booked)) => format!("{time:>8.1}s ({:+.1}s)", time - booked as f32),
+            (Some(time), None) => format!("{time:>8.1}s"),
+            (None, _) => "-".to_string(),
+        };
+        write!(f, "{:>6} platform {} | arr {} | dep {}", self.location, self.platform, actual(self.arrived, self.arrival), actual(self.departed, Some(self.departure)))
+    }
+}
+
 #[derive(Debug)]
-pub struct Driver <'a> {
-    tx: SyncSender<TrainMessage<'a>>,
-    rx: Receiver<SignallerMessage<'a>>,
-    pub train: Train<'a>,
-    src: &'a str,
-    pub dst: &'a str,
+pub struct Driver {
+    tx: UnboundedSender<TrainMessage>,
+    rx: UnboundedReceiver<SignallerMessage>,
+    pub train: Train,
+    src: BlockId,
+    pub dst: BlockId,
+    tail: VecDeque<(BlockId, f32)>, // blocks behind still under the train, and how far into the current block the front is when the rear leaves each
     delta_time: f32,
-    timetable: Vec<(&'a str, usize, u32)>,
+    time: f32,
+    pub timetable: Vec<Stop>,
+    next_stop: usize,
+    calling: bool, // current block is the next timetabled stop, so the train stops at its end whatever the signal shows
+    signal: (SignalColour, f32), // aspect and limit last given by the signaller
+    route: Vec<BlockId>, // every block from the start to the last timetabled location, empty to follow the points
+    reversible: bool, // current block ends at buffers and can be left in the opposite direction
+    reverse_requested: bool,
+    throttle_state: ThrottleState,
+    events: Vec<Event>,
+}
+
+// a driver part way through a run, everything but its channels to the signaller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriverState {
+    train: Train,
+    src: BlockId,
+    dst: BlockId,
+    tail: VecDeque<(BlockId, f32)>,
+    time: f32,
+    timetable: Vec<Stop>,
+    next_stop: usize,
+    calling: bool,
+    signal: (SignalColour, f32),
+    route: Vec<BlockId>,
+    reversible: bool,
+    reverse_requested: bool,
+    throttle_state: ThrottleState,
 }
 
-impl <'a> Driver <'a> {
-    pub fn new(tx: SyncSender<TrainMessage<'a>>, train: Train<'a>, dst: &'a str, delta_time: f32, timetable: Vec<(&'a str, usize, u32)>) -> Self {
-        let (signaller_tx, rx) = channel();
+impl Driver {
+    pub fn new(tx: UnboundedSender<TrainMessage>, train: Train, dst: BlockId, delta_time: f32, mut timetable: Vec<Stop>, route: Vec<BlockId>) -> Self {
+        let (signaller_tx, rx) = unbounded_channel();
+
+        // the booked time at the last stop is when the train is due in
+        if let Some(stop) = timetable.last_mut() {
+            stop.arrival = stop.arrival.or(Some(stop.departure));
+        }
 
         let driver = Driver {
             tx,
             rx,
             train,
-            src: "",
+            src: dst,
             dst,
+            tail: VecDeque::new(),
             delta_time,
+            time: 0.0,
             timetable,
+            next_stop: 0,
+            calling: false,
+            signal: (SignalColour::Red, 0.0),
+            route,
+            reversible: false,
+            reverse_requested: false,
+            throttle_state: ThrottleState::Brake,
+            events: Vec::new(),
         };
 
-        
-        driver.tx.send(TrainMessage::HelloWorld { tx: signaller_tx, train_id: driver.train.name, block_id: dst }).unwrap();
+        let stops = driver.timetable.iter().map(|stop| (stop.location, stop.platform)).collect();
+        driver.tx.send(TrainMessage::HelloWorld { tx: signaller_tx, train_id: driver.train.name, block_id: dst, route: driver.route.clone(), length: driver.train.length, stops }).unwrap();
 
         driver
     }
 
-    pub fn status(&self) -> (&'a str, &'a str) {
+    pub fn status(&self) -> (TrainId, BlockId) {
         (self.train.name, self.dst)
     }
 
+    pub fn snapshot(&self) -> DriverState {
+        DriverState {
+            train: self.train.clone(),
+            src: self.src,
+            dst: self.dst,
+            tail: self.tail.clone(),
+            time: self.time,
+            timetable: self.timetable.clone(),
+            next_stop: self.next_stop,
+            calling: self.calling,
+            signal: self.signal,
+            route: self.route.clone(),
+            reversible: self.reversible,
+            reverse_requested: self.reverse_requested,
+            throttle_state: self.throttle_state,
+        }
+    }
+
+    pub fn restore(&mut self, state: DriverState) -> Result<(), String> {
+        if state.train.name != self.train.name {
+            return Err(format!("expected train {} but found {}", self.train.name, state.train.name));
+        }
+        self.train = state.train;
+        self.src = state.src;
+        self.dst = state.dst;
+        self.tail = state.tail;
+        self.time = state.time;
+        self.timetable = state.timetable;
+        self.next_stop = state.next_stop;
+        self.calling = state.calling;
+        self.signal = state.signal;
+        self.route = state.route;
+        self.reversible = state.reversible;
+        self.reverse_requested = state.reverse_requested;
+        self.throttle_state = state.throttle_state;
+        Ok(())
+    }
+
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
     pub fn time_step(&mut self) {
-        loop { // process incoming messages from previous update step
-            match self.rx.try_recv() {
-                Ok(message) => {
-                    match message {
-                        SignallerMessage::NewBlock { new_block_id, colour, limit, length } => {
-                            self.train.position -= self.train.block_length; // subtract the previous block length from position to get ~0
-                            self.train.block_length = length as f32; // update for new block length
-                            self.src = self.dst;
-                            self.dst = new_block_id;
-                            
-                            debug!("{} entered block {}", self.train.name, new_block_id);
-                            self.adjust_speed(colour, limit as f32);
-                        },
-                        SignallerMessage::UpdateBlock { colour, limit} => {
-                            debug!("{} received signal update", self.train.name);
-                            self.adjust_speed(colour, limit as f32);
-                        },
+        while let Ok(message) = self.rx.try_recv() { // process incoming messages from previous update step
+            match message {
+                SignallerMessage::NewBlock { new_block_id, colour, limit, length, profile, reversed, reversible } => {
+                    if reversed {
+                        self.train.position = self.train.block_length - self.train.position + self.train.length; // reverse block starts where the old one ended, and the rear is now the front
+                        self.tail.clear(); // the signaller takes the whole train to have changed ends within the block
+                    }
+                    else if new_block_id != self.dst { // the first message is for the block the train starts in
+                        self.train.position -= self.train.block_length; // subtract the previous block length from position to get ~0
+                        for (_, clears_at) in self.tail.iter_mut() {
+                            *clears_at -= self.train.block_length;
+                        }
+                        self.tail.push_back((self.dst, self.train.length));
                     }
+                    self.train.block_length = length as f32; // update for new block length
+                    self.train.profile = profile;
+                    self.reversible = reversible;
+                    self.reverse_requested = false;
+                    self.src = self.dst;
+                    self.dst = new_block_id;
+                    self.calling = self.timetable.get(self.next_stop).is_some_and(|stop| stop.location == new_block_id);
+                    
+                    debug!("{} entered block {}", self.train.name, new_block_id);
+                    self.adjust_speed(colour, limit);
                 },
-                Err(_) => {            
-                    break;
+                SignallerMessage::UpdateBlock { colour, limit} => {
+                    debug!("{} received signal update", self.train.name);
+                    self.adjust_speed(colour, limit);
                 },
-            }        
-        };
+            }
+        }
 
         self.train.target_distance = self.train.block_length - self.train.position;
 
+        // brake for the stop once it is within reach, however fast the train is going
+        if self.calling && self.train.target_distance <= self.train.braking_distance() {
+            self.train.target_velocity = 0.0;
+            debug!("{} stopping at {}", self.train.name, self.dst);
+        }
+
+        while let Some(&(block_id, clears_at)) = self.tail.front() {
+            if self.train.position < clears_at {
+                break;
+            }
+            self.tx.send(TrainMessage::TailCleared { train_id: self.train.name, block_id }).unwrap();
+            self.tail.pop_front();
+            debug!("{} cleared block {}", self.train.name, block_id);
+        }
+
         // update train position and set signals
         if self.train.position > self.train.block_length {
             //todo: train pathfinding - handle with signaller tho
@@ -80,11 +226,74 @@ impl <'a> Driver <'a> {
             debug!("{} reserving next block", self.train.name);
         }
 
+        let stood = self.train.velocity == 0.0 && self.train.target_distance < 10.0;
+
+        if self.calling && stood {
+            self.call();
+        }
+
+        // change ends once stood at the buffers
+        if self.reversible && !self.reverse_requested && !self.calling && stood {
+            self.tx.send(TrainMessage::ReserveReverseBlock { train_id: self.train.name }).unwrap();
+            self.reverse_requested = true;
+            debug!("{} reversing", self.train.name);
+        }
+
         // update train
+        let emergency = self.train.emergency;
         self.train.update(self.delta_time);
+        self.time += self.delta_time;
+
+        if self.train.emergency && !emergency {
+            self.events.push(Event::EmergencyBrake { train_id: self.train.name, block_id: self.dst, velocity: self.train.velocity });
+        }
+        let throttle_state = ThrottleState::of(self.train.throttle, self.train.emergency, self.throttle_state);
+        if throttle_state != self.throttle_state {
+            self.throttle_state = throttle_state;
+            self.events.push(Event::ThrottleChanged { train_id: self.train.name, state: throttle_state, throttle: self.train.throttle });
+        }
+    }
+
+    // record arrival, then once the dwell is up and the booked departure time has come, ask for the road
+    // (a train changing ends asks to reverse instead, and a train at its last stop stays put)
+    fn call(&mut self) {
+        let last = self.next_stop + 1 == self.timetable.len();
+        let stop = &mut self.timetable[self.next_stop];
+
+        if stop.arrived.is_none() {
+            stop.arrived = Some(self.time);
+            self.events.push(Event::Arrived { train_id: self.train.name, location: stop.location, platform: stop.platform, booked: stop.arrival });
+            info!("{} arrived at {} platform {} at {:.1}s", self.train.name, stop.location, stop.platform, self.time);
+        }
+
+        let due = stop.arrived.unwrap() + stop.dwell as f32;
+        if last || self.time < due.max(stop.departure as f32) {
+            return;
+        }
+
+        stop.departed = Some(self.time);
+        self.events.push(Event::Departed { train_id: self.train.name, location: stop.location, platform: stop.platform, booked: stop.departure });
+        info!("{} departed {} platform {} at {:.1}s", self.train.name, stop.location, stop.platform, self.time);
+        self.next_stop += 1;
+        self.calling = false;
+
+        if !self.reversible {
+            self.tx.send(TrainMessage::RequestRoute { train_id: self.train.name }).unwrap();
+        }
+        self.adjust_speed(self.signal.0, self.signal.1);
     }
 
     fn adjust_speed(&mut self, colour: SignalColour, limit: f32) {
+        self.signal = (colour, limit);
+        let limit = limit.min(self.train.max_speed);
+
+        // a train calling here stops short of the signal whatever it shows, so runs at line speed until braking for the stop
+        if self.calling {
+            self.train.target_velocity = limit;
+            debug!("{} calling at {}", self.train.name, self.dst);
+            return;
+        }
+
         match colour {
             SignalColour::Red => {
                 self.train.target_velocity = 0.0;
@@ -103,8 +312,57 @@ impl <'a> Driver <'a> {
     }
 }
 
-impl <'a> Display for Driver <'a> {
+impl Agent for Driver {
+    fn step(&mut self) {
+        self.time_step();
+    }
+}
+
+impl Display for Driver {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:>8} | {} |", self.train, self.train.name)
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_call() {
+    let (tx, mut rx) = unbounded_channel();
+    let timetable = vec![Stop::new("A".into(), 1, None, 10, 5), Stop::new("B".into(), 2, None, 20, 0)];
+    let rolling_stock = crate::utils::io::parse_rolling_stock(crate::utils::io::ROLLING_STOCK).unwrap();
+    let train = Train::new("1A01".into(), crate::utils::io::find_class(&rolling_stock, "802").unwrap());
+    let mut driver = Driver::new(tx, train, "A".into(), 0.5, timetable, Vec::new());
+    driver.calling = true; // stood at the end of A
+
+    while driver.timetable[0].departed.is_none() {
+        driver.time_step();
+    }
+
+    assert_eq!(driver.timetable[0].arrived, Some(0.0));
+    assert_eq!(driver.timetable[0].departed, Some(10.0)); // booked departure is later than the dwell
+    assert_eq!(driver.timetable[1].arrival, Some(20));
+    assert!(std::iter::from_fn(|| rx.try_recv().ok()).any(|message| matches!(message, TrainMessage::RequestRoute { train_id } if train_id == "1A01")));
+}
+
+#[test]
+fn test_calling_approach() {
+    let (tx, mut rx) = unbounded_channel();
+    let timetable = vec![Stop::new("B".into(), 1, None, 1000, 30)];
+    let rolling_stock = crate::utils::io::parse_rolling_stock(crate::utils::io::ROLLING_STOCK).unwrap();
+    let train = Train::new("1A01".into(), crate::utils::io::find_class(&rolling_stock, "802").unwrap());
+    let mut driver = Driver::new(tx, train, "A".into(), 0.1, timetable, Vec::new());
+    let Some(TrainMessage::HelloWorld { tx: signaller_tx, .. }) = rx.try_recv().ok() else { panic!("no greeting") };
+
+    // into the block it calls at slowly and with the signal beyond at danger, as when it was held at the one before
+    driver.train.velocity = 5.0;
+    signaller_tx.send(SignallerMessage::NewBlock { new_block_id: "B".into(), colour: SignalColour::Red, limit: 40.0, length: 2000, profile: Vec::new(), reversed: false, reversible: false }).unwrap();
+    let mut fastest: f32 = 0.0;
+    while driver.timetable[0].arrived.is_none() && driver.time < 600.0 {
+        driver.time_step();
+        fastest = fastest.max(driver.train.velocity);
+    }
+
+    // it picks up to line speed rather than creeping the length of the block, and still stops short of the signal
+    assert!(fastest > 20.0, "only reached {fastest}m/s");
+    assert!(driver.timetable[0].arrived.is_some_and(|arrived| arrived < 150.0), "arrived at {:?}", driver.timetable[0].arrived);
+    assert!(driver.train.position <= 2000.0 && !driver.train.emergency);
+}
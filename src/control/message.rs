@@ -1,15 +1,34 @@
-use std::sync::mpsc::Sender;
+use tokio::sync::mpsc::UnboundedSender;
 
-use crate::infrastructure::signal::SignalColour;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
-pub enum TrainMessage <'m> {
-    HelloWorld { tx: Sender<SignallerMessage<'m>>, train_id: &'m str, block_id: &'m str },
-    ReserveNextBlock { train_id: &'m str }
+use crate::{infrastructure::signal::SignalColour, utils::{id::{BlockId, TrainId}, surface::Surface}};
+
+// everything but the greeting can be waiting in a snapshot, the signaller's end of a channel can't be saved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TrainMessage {
+    #[serde(skip)]
+    HelloWorld { tx: UnboundedSender<SignallerMessage>, train_id: TrainId, block_id: BlockId, route: Vec<BlockId>, length: f32, stops: Vec<(BlockId, usize)> },
+    ReserveNextBlock { train_id: TrainId },
+    ReserveReverseBlock { train_id: TrainId },
+    RequestRoute { train_id: TrainId }, // ready to leave a timetabled stop
+    TailCleared { train_id: TrainId, block_id: BlockId }, // the rear of the train has left a block behind it
+}
+
+impl TrainMessage {
+    pub fn train_id(&self) -> TrainId {
+        match self {
+            TrainMessage::HelloWorld { train_id, .. } |
+            TrainMessage::ReserveNextBlock { train_id } |
+            TrainMessage::ReserveReverseBlock { train_id } |
+            TrainMessage::RequestRoute { train_id } |
+            TrainMessage::TailCleared { train_id, .. } => *train_id,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub enum SignallerMessage <'m> {
-    NewBlock { new_block_id: &'m str, colour: SignalColour, limit: f32, length: u32 },
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SignallerMessage {
+    NewBlock { new_block_id: BlockId, colour: SignalColour, limit: f32, length: u32, profile: Vec<Surface>, reversed: bool, reversible: bool },
     UpdateBlock { colour: SignalColour, limit: f32 }
 }
\ No newline at end of file
@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use crate::utils::id::{BlockId, TrainId};
+
+// which trains are in which blocks, looked up either way round
+// a train's front is in one block and its body may still be in blocks behind it,
+// and a block can hold several trains at once (platforms, permissive working, sidings, joining and splitting)
+#[derive(Debug, Default)]
+pub struct Occupancy {
+    trains: HashMap<TrainId, VecDeque<BlockId>>, // train id and the blocks it is in, rearmost first and its front last
+    blocks: HashMap<BlockId, BTreeSet<TrainId>>, // block id and the trains in it, ordered so lookups repeat exactly
+}
+
+impl Occupancy {
+    pub fn new() -> Self {
+        Occupancy::default()
+    }
+
+    // every train and the blocks it is in, rearmost first, for saving
+    pub fn positions(&self) -> BTreeMap<TrainId, Vec<BlockId>> {
+        self.trains.iter().map(|(train_id, blocks)| (*train_id, blocks.iter().copied().collect())).collect()
+    }
+
+    pub fn from_positions(positions: BTreeMap<TrainId, Vec<BlockId>>) -> Self {
+        let mut occupancy = Occupancy::new();
+        for (train_id, blocks) in positions {
+            for block_id in blocks {
+                occupancy.enter(train_id, block_id);
+            }
+        }
+        occupancy
+    }
+
+    // the front of a train runs into a block, anything behind stays occupied until cleared
+    pub fn enter(&mut self, train_id: TrainId, block_id: BlockId) {
+        let blocks = self.trains.entry(train_id).or_default();
+        if blocks.back() == Some(&block_id) {
+            return;
+        }
+        blocks.push_back(block_id);
+        self.blocks.entry(block_id).or_default().insert(train_id);
+    }
+
+    // the rear of a train has left a block
+    pub fn clear(&mut self, train_id: TrainId, block_id: BlockId) {
+        let Some(blocks) = self.trains.get_mut(&train_id) else {
+            return;
+        };
+        if let Some(index) = blocks.iter().position(|id| *id == block_id) {
+            blocks.remove(index);
+        }
+        if !blocks.contains(&block_id) {
+            if let Some(trains) = self.blocks.get_mut(&block_id) {
+                trains.remove(&train_id);
+                if trains.is_empty() {
+                    self.blocks.remove(&block_id);
+                }
+            }
+        }
+    }
+
+    pub fn front(&self, train_id: TrainId) -> Option<BlockId> {
+        self.trains.get(&train_id).and_then(|blocks| blocks.back()).copied()
+    }
+
+    // blocks a train's body is still in behind its front, rearmost first
+    pub fn behind(&self, train_id: TrainId) -> Vec<BlockId> {
+        self.trains.get(&train_id).map_or(Vec::new(), |blocks| blocks.iter().take(blocks.len().saturating_sub(1)).copied().collect())
+    }
+
+    pub fn trains(&self, block_id: BlockId) -> impl Iterator<Item = TrainId> + '_ {
+        self.blocks.get(&block_id).into_iter().flatten().copied()
+    }
+
+    // trains whose front is in a block, so are approaching its signal
+    pub fn fronts(&self, block_id: BlockId) -> impl Iterator<Item = TrainId> + '_ {
+        self.trains(block_id).filter(move |train_id| self.front(*train_id) == Some(block_id))
+    }
+
+    pub fn is_occupied(&self, block_id: BlockId) -> bool {
+        self.blocks.contains_key(&block_id)
+    }
+
+    // anything in a block apart from the given train
+    pub fn is_occupied_by_other(&self, block_id: BlockId, train_id: TrainId) -> bool {
+        self.trains(block_id).any(|other| other != train_id)
+    }
+}
+
+#[test]
+fn test_occupancy() {
+    let mut occupancy = Occupancy::new();
+    occupancy.enter("1A01".into(), "A".into());
+    occupancy.enter("1A01".into(), "B".into());
+    occupancy.enter("2B02".into(), "B".into());
+
+    assert_eq!(occupancy.front("1A01".into()), Some("B".into()));
+    assert_eq!(occupancy.behind("1A01".into()), vec!["A"]);
+    assert_eq!(occupancy.trains("B".into()).collect::<Vec<_>>(), vec!["1A01", "2B02"]);
+    assert!(occupancy.is_occupied_by_other("B".into(), "1A01".into()));
+
+    occupancy.clear("1A01".into(), "A".into());
+    assert!(!occupancy.is_occupied("A".into()));
+    assert!(occupancy.behind("1A01".into()).is_empty());
+    assert_eq!(occupancy.trains("B".into()).count(), 2);
+}
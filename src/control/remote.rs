@@ -0,0 +1,351 @@
+use crate::{
+    control::message::{SignallerMessage, TrainMessage},
+    infrastructure::signal::SignalColour,
+    utils::{id::{BlockId, TrainId}, io::{DEAD_END, Network}, surface::Surface}
+};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    runtime::Handle,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+};
+
+// the first line from anything connecting, a driver greeting the signaller just as one in the simulation does
+// (less the channel back, which is the connection) or a signaller taking over from the simulation's own, its names
+// left as they came until the greeting is admitted so that a refused one leaves nothing interned behind
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Hello {
+    HelloWorld {
+        train_id: String,
+        block_id: String,
+        #[serde(default)]
+        route: Vec<String>,
+        length: f32,
+        #[serde(default)]
+        stops: Vec<(String, usize)>,
+    },
+    HelloSignaller,
+}
+
+// a greeting let in, with the names made ids
+enum Admitted {
+    Driver { train_id: TrainId, block_id: BlockId, route: Vec<BlockId>, length: f32, stops: Vec<(BlockId, usize)> },
+    Signaller,
+}
+
+// what an admitted driver sends, as a train message but with the names looked up rather than interned
+#[derive(Deserialize)]
+enum Report {
+    ReserveNextBlock { train_id: String },
+    ReserveReverseBlock { train_id: String },
+    RequestRoute { train_id: String },
+    TailCleared { train_id: String, block_id: String },
+}
+
+// a remote signaller's message for a train, once its names have been found to be ones the simulation has
+#[derive(Debug, Clone)]
+pub struct Addressed {
+    pub train_id: TrainId,
+    pub message: SignallerMessage,
+}
+
+// what a remote signaller sends, each message for the train it is addressed to, as it came
+#[derive(Deserialize)]
+struct Reply {
+    train_id: String,
+    message: Answer,
+}
+
+// as a signaller message but with the names looked up rather than interned
+#[derive(Deserialize)]
+enum Answer {
+    NewBlock { new_block_id: String, colour: SignalColour, limit: f32, length: u32, profile: Vec<Surface>, reversed: bool, reversible: bool },
+    UpdateBlock { colour: SignalColour, limit: f32 },
+}
+
+#[derive(Serialize)]
+struct Refusal<'a> {
+    error: &'a str,
+}
+
+// a line for a connection to send, or a refusal to send before closing it
+enum Outgoing {
+    Line(String),
+    Refusal(String),
+}
+
+type Reader = Box<dyn AsyncBufRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+type Connection = (Reader, UnboundedSender<Outgoing>); // what it sends, and what goes out to it
+
+// where remote drivers and signallers connect, "tcp:<host>:<port>" or "unix:<path>"
+pub struct Gateway {
+    endpoint: String,
+    signallers: UnboundedReceiver<Connection>,
+    shared: Shared,
+    runtime: Handle,
+}
+
+// what every connection needs to hand its messages over
+#[derive(Clone)]
+struct Shared {
+    train_tx: UnboundedSender<TrainMessage>,
+    network: Arc<HashSet<(BlockId, BlockId)>>, // every way from one block into another
+    blocks: Arc<HashSet<BlockId>>,
+    trains: Arc<Mutex<HashSet<TrainId>>>, // ids already taken, by the scenario or by a driver already connected
+    signaller_taken: Arc<AtomicBool>, // set from the start when the simulation keeps its own signaller
+    signallers: UnboundedSender<Connection>,
+}
+
+impl Gateway {
+    // connections are served alongside the agents, each on a task of its own
+    pub fn open(endpoint: &str, train_tx: UnboundedSender<TrainMessage>, network: &Network, trains: &[TrainId], remote_signaller: bool, runtime: &Handle) -> Result<Self, String> {
+        let (signallers_tx, signallers) = unbounded_channel();
+        let shared = Shared {
+            train_tx,
+            network: Arc::new(network.all_edges().map(|(block_id, next_block_id, _)| (block_id, next_block_id)).collect()),
+            blocks: Arc::new(network.nodes().filter(|block_id| *block_id != DEAD_END).collect()),
+            trains: Arc::new(Mutex::new(trains.iter().copied().collect())),
+            signaller_taken: Arc::new(AtomicBool::new(!remote_signaller)),
+            signallers: signallers_tx,
+        };
+
+        let endpoint = match endpoint.split_once(':') {
+            Some(("tcp", address)) => {
+                let listener = std::net::TcpListener::bind(address).map_err(|e| format!("could not listen on {address}: {e}"))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                let address = listener.local_addr().map_err(|e| e.to_string())?;
+                runtime.spawn(serve_tcp(listener, shared.clone()));
+                format!("tcp:{address}")
+            },
+            #[cfg(unix)]
+            Some(("unix", path)) => {
+                let listener = std::os::unix::net::UnixListener::bind(path).map_err(|e| format!("could not listen on {path}: {e}"))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                runtime.spawn(serve_unix(listener, shared.clone()));
+                format!("unix:{path}")
+            },
+            _ => return Err(format!("unknown endpoint {endpoint}, expected tcp:<host>:<port> or unix:<path>")),
+        };
+        info!("listening for drivers and signallers on {endpoint}");
+
+        Ok(Gateway { endpoint, signallers, shared, runtime: runtime.clone() })
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    // hold the run until a signaller has connected
+    pub fn await_signaller(&mut self) -> RemoteSignaller {
+        let (reader, outgoing) = self.signallers.blocking_recv().unwrap();
+        let (replies_tx, replies) = unbounded_channel();
+        let (refusals, shared) = (outgoing.clone(), self.shared.clone());
+        self.runtime.spawn(read_lines(reader, outgoing.clone(), move |reply: Reply| match address(reply, &shared) {
+            Ok(addressed) => replies_tx.send(addressed).is_ok(),
+            Err(error) => {
+                // the signaller carries on, only this reply is dropped
+                warn!("dropped reply from remote signaller: {error}");
+                let _ = refusals.send(Outgoing::Line(refusal(&error)));
+                true
+            },
+        }));
+        RemoteSignaller { outgoing, replies }
+    }
+}
+
+async fn serve_tcp(listener: std::net::TcpListener, shared: Shared) {
+    let listener = TcpListener::from_std(listener).unwrap();
+    while let Ok((stream, _)) = listener.accept().await {
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        tokio::spawn(accept(Box::new(BufReader::new(reader)), Box::new(writer), shared.clone()));
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(listener: std::os::unix::net::UnixListener, shared: Shared) {
+    let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+    while let Ok((stream, _)) = listener.accept().await {
+        let (reader, writer) = stream.into_split();
+        tokio::spawn(accept(Box::new(BufReader::new(reader)), Box::new(writer), shared.clone()));
+    }
+}
+
+// whichever role a connection takes, everything it is sent goes out through one task
+async fn accept(mut reader: Reader, writer: Writer, shared: Shared) {
+    let (outgoing, lines) = unbounded_channel();
+    tokio::spawn(write_lines(writer, lines));
+
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+        return;
+    }
+    let hello = serde_json::from_str(&line).map_err(|e| e.to_string()).and_then(|hello| admit(hello, &shared));
+    match hello {
+        Ok(Admitted::Driver { train_id, block_id, route, length, stops }) => {
+            debug!("remote driver {train_id} connected at {block_id}");
+            let (tx, rx) = unbounded_channel();
+            if shared.train_tx.send(TrainMessage::HelloWorld { tx, train_id, block_id, route, length, stops }).is_err() {
+                return;
+            }
+            tokio::spawn(forward(rx, outgoing.clone()));
+            read_lines(reader, outgoing.clone(), |report: Report| match check(report, train_id, &shared) {
+                Ok(message) => shared.train_tx.send(message).is_ok(),
+                Err(error) => {
+                    let _ = outgoing.send(Outgoing::Refusal(error));
+                    false
+                },
+            }).await;
+            debug!("remote driver {train_id} disconnected");
+        },
+        Ok(Admitted::Signaller) => {
+            debug!("remote signaller connected");
+            let _ = shared.signallers.send((reader, outgoing));
+        },
+        Err(error) => {
+            let _ = outgoing.send(Outgoing::Refusal(error));
+        },
+    }
+}
+
+fn refusal(error: &str) -> String {
+    serde_json::to_string(&Refusal { error }).unwrap()
+}
+
+// until the connection has gone or a refusal has been sent, when it is closed
+async fn write_lines(mut writer: Writer, mut lines: UnboundedReceiver<Outgoing>) {
+    while let Some(outgoing) = lines.recv().await {
+        let (line, last) = match outgoing {
+            Outgoing::Line(line) => (line, false),
+            Outgoing::Refusal(error) => (refusal(&error), true),
+        };
+        let written = writer.write_all(format!("{line}\n").as_bytes()).await.is_ok() && writer.flush().await.is_ok();
+        if !written || last {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+// a driver can only speak for its own train, and only of blocks in the network
+fn check(report: Report, train_id: TrainId, shared: &Shared) -> Result<TrainMessage, String> {
+    let (Report::ReserveNextBlock { train_id: name } | Report::ReserveReverseBlock { train_id: name } |
+        Report::RequestRoute { train_id: name } | Report::TailCleared { train_id: name, .. }) = &report;
+    if name != train_id.as_str() {
+        return Err(format!("this connection drives {train_id}, not {name}"));
+    }
+    Ok(match report {
+        Report::ReserveNextBlock { .. } => TrainMessage::ReserveNextBlock { train_id },
+        Report::ReserveReverseBlock { .. } => TrainMessage::ReserveReverseBlock { train_id },
+        Report::RequestRoute { .. } => TrainMessage::RequestRoute { train_id },
+        Report::TailCleared { block_id, .. } => TrainMessage::TailCleared { train_id, block_id: known_block(&block_id, shared)? },
+    })
+}
+
+// a signaller can only answer trains the simulation has, and only with blocks in the network
+fn address(reply: Reply, shared: &Shared) -> Result<Addressed, String> {
+    let train_id = TrainId::lookup(&reply.train_id).filter(|train_id| shared.trains.lock().unwrap().contains(train_id))
+        .ok_or_else(|| format!("unknown train {}", reply.train_id))?;
+    let message = match reply.message {
+        Answer::NewBlock { new_block_id, colour, limit, length, profile, reversed, reversible } => {
+            SignallerMessage::NewBlock { new_block_id: known_block(&new_block_id, shared)?, colour, limit, length, profile, reversed, reversible }
+        },
+        Answer::UpdateBlock { colour, limit } => SignallerMessage::UpdateBlock { colour, limit },
+    };
+    Ok(Addressed { train_id, message })
+}
+
+// every block in the network is interned already, so a name that isn't can't be one
+fn known_block(name: &str, shared: &Shared) -> Result<BlockId, String> {
+    BlockId::lookup(name).filter(|block_id| shared.blocks.contains(block_id)).ok_or_else(|| format!("unknown block {name}"))
+}
+
+fn admit(hello: Hello, shared: &Shared) -> Result<Admitted, String> {
+    match hello {
+        Hello::HelloWorld { train_id, block_id, route, length, stops } => {
+            let block_id = known_block(&block_id, shared)?;
+            let route = route.iter().map(|name| known_block(name, shared)).collect::<Result<Vec<_>, _>>()?;
+            let stops = stops.iter().map(|(name, platform)| Ok((known_block(name, shared)?, *platform))).collect::<Result<Vec<_>, String>>()?;
+            if route.first().is_some_and(|start| *start != block_id) || route.windows(2).any(|pair| !shared.network.contains(&(pair[0], pair[1]))) {
+                return Err("route must run from the block the train is in, through one block into the next".to_string());
+            }
+            if stops.iter().any(|(_, platform)| *platform == 0) {
+                return Err("platforms are numbered from 1".to_string());
+            }
+            // the train's name is only interned once everything else about it has been let in
+            let mut trains = shared.trains.lock().unwrap();
+            if TrainId::lookup(&train_id).is_some_and(|taken| trains.contains(&taken)) {
+                return Err(format!("train {train_id} is already running"));
+            }
+            let train_id = TrainId::new(&train_id);
+            trains.insert(train_id);
+            Ok(Admitted::Driver { train_id, block_id, route, length, stops })
+        },
+        Hello::HelloSignaller => {
+            // only ever one, and only when the simulation was asked to wait for it
+            if shared.signaller_taken.swap(true, Ordering::SeqCst) {
+                return Err("the simulation already has a signaller".to_string());
+            }
+            Ok(Admitted::Signaller)
+        },
+    }
+}
+
+// one JSON object per line until the connection closes, a bad line, which is refused, or `keep_going` returning false
+async fn read_lines<T: for<'de> Deserialize<'de>>(reader: Reader, outgoing: UnboundedSender<Outgoing>, mut keep_going: impl FnMut(T) -> bool) {
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(message) => if !keep_going(message) {
+                break;
+            },
+            Err(e) => {
+                debug!("bad message {line}: {e}");
+                let _ = outgoing.send(Outgoing::Refusal(format!("bad message: {e}")));
+                break;
+            },
+        }
+    }
+}
+
+// the signaller never expects its messages to go missing, so they're still taken once the connection has gone
+async fn forward(mut rx: UnboundedReceiver<SignallerMessage>, outgoing: UnboundedSender<Outgoing>) {
+    while let Some(message) = rx.recv().await {
+        let _ = outgoing.send(Outgoing::Line(serde_json::to_string(&message).unwrap()));
+    }
+}
+
+pub struct RemoteSignaller {
+    outgoing: UnboundedSender<Outgoing>, // shared with the replies' reader, which answers any it can't take
+    replies: UnboundedReceiver<Addressed>,
+}
+
+impl RemoteSignaller {
+    // every train's messages, the greetings as they would have come over a connection
+    pub fn send(&mut self, messages: Vec<TrainMessage>) {
+        for message in messages {
+            let line = match message {
+                TrainMessage::HelloWorld { train_id, block_id, route, length, stops, .. } => serde_json::to_string(&Hello::HelloWorld {
+                    train_id: train_id.to_string(),
+                    block_id: block_id.to_string(),
+                    route: route.iter().map(BlockId::to_string).collect(),
+                    length,
+                    stops: stops.iter().map(|(block_id, platform)| (block_id.to_string(), *platform)).collect(),
+                }),
+                message => serde_json::to_string(&message),
+            };
+            let _ = self.outgoing.send(Outgoing::Line(line.unwrap()));
+        }
+    }
+
+    pub fn replies(&mut self) -> Vec<Addressed> {
+        std::iter::from_fn(|| self.replies.try_recv().ok()).collect()
+    }
+}
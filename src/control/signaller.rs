@@ -1,32 +1,60 @@
-use log::debug;
+use log::{debug, warn};
 use petgraph::Direction::Incoming;
 use petgraph::Direction::Outgoing;
-use petgraph::graphmap::DiGraphMap;
-use crate::infrastructure::train;
 use crate::{
     infrastructure::{
-        signal::{Owner, SignalColour}, block::{Block, BlockType}
+        signal::{Owner, Signal, SignalColour}, block::{Block, BlockState, BlockType}, platform::Platform
     },
     control::{
-        message::{SignallerMessage, TrainMessage},
+        ars::Regulation, clock::Agent, console::Command, message::{SignallerMessage, TrainMessage}, occupancy::Occupancy,
     },
     utils::{
-        bihashmap::BiHashMap
+        id::{BlockId, SignalId, TrainId}, io::{DEAD_END, Network}, events::{Event, Request, owner_name}
     }
 };
-use rayon::prelude::*;
-use std::sync::mpsc::channel;
+use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex, mpsc::{Sender, Receiver}};
+use std::{cell::RefCell, collections::{BTreeMap, BTreeSet, HashMap, VecDeque}};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 
 #[derive(Debug)]
-pub struct Signaller <'a> {
-    tx: HashMap<&'a str, Sender<SignallerMessage<'a>>>, 
-    rx: Receiver<TrainMessage<'a>>,
-    pub network: DiGraphMap::<&'a str, Arc<Mutex<Block<'a>>>>, // track id and its SUBSEQUENT tracks
-    pub train_positions: BiHashMap<&'a str, &'a str>
+pub struct Signaller {
+    tx: HashMap<TrainId, UnboundedSender<SignallerMessage>>, 
+    rx: UnboundedReceiver<TrainMessage>,
+    inbox: Vec<TrainMessage>, // received but not yet acted on
+    pub network: Network, // track id and its SUBSEQUENT tracks
+    pub occupancy: Occupancy,
+    train_routes: HashMap<TrainId, (Vec<BlockId>, usize)>, // blocks each train is to pass through and how far along it is
+    train_lengths: HashMap<TrainId, f32>,
+    train_stops: HashMap<TrainId, VecDeque<(BlockId, usize)>>, // timetabled locations still to call at and the platform booked at each
+    routes: HashMap<BlockId, (BlockId, TrainId)>, // block id the route starts from, the block it leads into and the train it is set for
+    direction_locks: HashMap<BlockId, TrainId>, // bidirectional block id (in the locked direction) and the train holding it
+    waiting: Vec<(TrainId, BlockId, bool)>, // train id, block id, reversing - trains held at a signal until a route can be set
+    held_signals: BTreeSet<BlockId>, // signals put back to danger by hand, which stay there until released
+    regulation: Regulation, // set afresh every tick by automatic route setting, if it is running
+    avoid_deadlocks: bool, // refuse any movement that would leave trains waiting on each other for good
+    deadlocked: BTreeSet<TrainId>, // trains last reported as waiting on each other for good
+    events: RefCell<Vec<Event>>, // signals are changed through shared references
+}
+
+// what the signaller has set up and been asked for part way through a run, along with every block's signals,
+// points and platforms, taken between ticks once the drivers have read everything sent to them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignallerState {
+    blocks: BTreeMap<BlockId, BlockState>,
+    occupancy: BTreeMap<TrainId, Vec<BlockId>>,
+    train_routes: BTreeMap<TrainId, (Vec<BlockId>, usize)>,
+    train_lengths: BTreeMap<TrainId, f32>,
+    train_stops: BTreeMap<TrainId, VecDeque<(BlockId, usize)>>,
+    routes: BTreeMap<BlockId, (BlockId, TrainId)>,
+    direction_locks: BTreeMap<BlockId, TrainId>,
+    waiting: Vec<(TrainId, BlockId, bool)>,
+    held_signals: BTreeSet<BlockId>,
+    #[serde(default)]
+    deadlocked: BTreeSet<TrainId>,
+    inbox: Vec<TrainMessage>,
 }
 
 // signaller controls everything (even trains, which relay information after every update)
@@ -35,93 +63,1279 @@ pub struct Signaller <'a> {
 // # signaller 
 // - trains (mut)
 // - signals + tracks between each (mut)
+// - points (mut)
 
-impl <'a> Signaller <'a>{
-    pub fn new(rx: Receiver<TrainMessage<'a>>, network: DiGraphMap<&'a str, Arc<Mutex<Block<'a>>>>) -> Self {
-        Signaller {
-            tx: HashMap::<&'a str, Sender<SignallerMessage<'a>>>::new(),
+impl Signaller {
+    pub fn new(rx: UnboundedReceiver<TrainMessage>, network: Network) -> Self {
+        let signaller = Signaller {
+            tx: HashMap::new(),
             rx,
+            inbox: Vec::new(),
             network,
-            train_positions: BiHashMap::new()
+            occupancy: Occupancy::new(),
+            train_routes: HashMap::new(),
+            train_lengths: HashMap::new(),
+            train_stops: HashMap::new(),
+            routes: HashMap::new(),
+            direction_locks: HashMap::new(),
+            waiting: Vec::new(),
+            held_signals: BTreeSet::new(),
+            regulation: Regulation::default(),
+            avoid_deadlocks: false,
+            deadlocked: BTreeSet::new(),
+            events: RefCell::new(Vec::new()),
+        };
+
+        // blocks with nowhere to go end at buffer stops, and signals in front of points only clear for a route,
+        // so both are held at danger
+        let held: Vec<BlockId> = signaller.network.nodes().filter(|block_id| {
+            signaller.network.neighbors_directed(*block_id, Incoming).next().is_some() && 
+            (signaller.network.neighbors_directed(*block_id, Outgoing).next().is_none() || signaller.is_controlled(*block_id))
+        }).collect();
+
+        for block_id in held {
+            signaller.propagate_signal(block_id, Owner::Signaller, SignalColour::Red);
         }
+
+        signaller
+    }
+
+    pub fn avoid_deadlocks(&mut self) {
+        self.avoid_deadlocks = true;
+    }
+
+    pub fn take_events(&self) -> Vec<Event> {
+        self.events.take()
     }
 
     pub fn update(&mut self) {
-        loop { // process incoming messages from previous update step
-            match self.rx.try_recv() {
-                Ok(message) => {
-                    match message {
-                        TrainMessage::HelloWorld { tx, train_id, block_id } => {
-                            self.tx.insert(train_id, tx);
-                            let prev_block_id = self.prev_in_path(train_id, block_id);
-                            self.train_positions.insert(&block_id, &train_id);
-                            self.reserve_block(prev_block_id, block_id, train_id);
+        // drivers run in parallel so their messages arrive in any order, handle them in train order instead
+        self.receive();
+        let mut messages = std::mem::take(&mut self.inbox);
+        messages.sort_by_key(|message| message.train_id());
+
+        for message in messages {
+            if let Err(e) = self.check_message(&message) {
+                warn!("ignored message from {}: {e}", message.train_id());
+                continue;
+            }
+
+            let request = match message {
+                TrainMessage::HelloWorld { .. } => None,
+                TrainMessage::ReserveNextBlock { .. } => Some(Request::NextBlock),
+                TrainMessage::ReserveReverseBlock { .. } => Some(Request::ReverseBlock),
+                TrainMessage::RequestRoute { .. } => Some(Request::Route),
+                TrainMessage::TailCleared { .. } => None,
+            };
+            if let Some(request) = request {
+                let train_id = message.train_id();
+                self.events.borrow_mut().push(Event::Requested { train_id, block_id: self.position(train_id), request });
+            }
+
+            match message {
+                TrainMessage::HelloWorld { tx, train_id, block_id, route, length, stops } => {
+                    self.tx.insert(train_id, tx);
+                    self.train_lengths.insert(train_id, length);
+                    self.train_stops.insert(train_id, stops.into());
+                    if !route.is_empty() {
+                        self.train_routes.insert(train_id, (route, 0));
+                    }
+                    self.occupancy.enter(train_id, block_id);
+                    let prev_block_id = self.prev_in_path(train_id);
+                    if self.is_bidirectional(block_id) && !self.try_lock(train_id, block_id) {
+                        debug!("{} placed on {} against an opposing movement", train_id, block_id);
+                        self.direction_locks.insert(block_id, train_id);
+                    }
+                    self.reserve_block(prev_block_id, block_id, train_id, false);
+                },
+                TrainMessage::ReserveNextBlock { train_id } => {
+                    let block_id = self.position(train_id);
+                    match self.next_in_path(train_id).filter(|next_block_id| !self.is_reversal(block_id, *next_block_id)) {
+                        Some(next_block_id) => {
+                            if self.routes.get(&block_id) != Some(&(next_block_id, train_id)) {
+                                debug!("{} entered {} without a route", train_id, next_block_id);
+                                if self.is_bidirectional(next_block_id) {
+                                    self.try_lock(train_id, next_block_id);
+                                }
+                            }
+                            self.reserve_block(block_id, next_block_id, train_id, false);
+                        },
+                        None => {
+                            debug!("{} has no block to enter after {}", train_id, block_id);
                         },
-                        TrainMessage::ReserveNextBlock { train_id } => {
-                            let block_id = *self.train_positions.get(&"", &train_id).1.unwrap();
-                            let next_block_id= self.next_in_path(train_id, block_id);
-                            self.train_positions.remove(&"", &train_id);
-                            self.train_positions.insert(&next_block_id, &train_id);
-                            self.reserve_block(block_id, next_block_id, train_id);
-                        }
                     }
                 },
-                Err(_) => {
-                    break
+                TrainMessage::RequestRoute { train_id } => {
+                    let block_id = self.position(train_id);
+                    if self.next_in_path(train_id).is_some() {
+                        self.request_route(train_id, block_id);
+                    }
+                },
+                TrainMessage::TailCleared { train_id, block_id } => {
+                    self.vacate(train_id, block_id);
+                },
+                TrainMessage::ReserveReverseBlock { train_id } => {
+                    let block_id = self.position(train_id);
+                    if !self.try_reverse(train_id, block_id) {
+                        debug!("{} waiting to reverse in {}", train_id, block_id);
+                        self.waiting.push((train_id, block_id, true));
+                    }
                 },
             }
-        };
+        }
+
+        self.detect_deadlock();
     }
 
-    fn reserve_block(&self, block_id: &'a str, next_block_id: &'a str, train_id: &'a str) {
-        debug!("reserving block {} for {}", next_block_id, train_id);
-        let next_block = &self.network.edge_weight(block_id, next_block_id).unwrap().lock().unwrap();
-        
-        match &next_block.block_type {
-            BlockType::Track { signal } => {
-                self.tx.get(train_id).unwrap().send(SignallerMessage::NewBlock { 
-                    new_block_id: next_block_id, 
-                    colour: signal.colour,
-                    limit: next_block.limit, 
-                    length: next_block.length,
-                }).unwrap();
+    // anything from a driver that doesn't fit what the signaller knows, which a remote one could send, is left alone
+    fn check_message(&self, message: &TrainMessage) -> Result<(), String> {
+        match message {
+            TrainMessage::HelloWorld { block_id, .. } => self.check_block(*block_id),
+            TrainMessage::TailCleared { train_id, block_id } => {
+                self.check_block(*block_id)?;
+                if !self.occupancy.trains(*block_id).any(|id| id == *train_id) {
+                    return Err(format!("{train_id} is not in {block_id}"));
+                }
+                Ok(())
             },
-            BlockType::Station { platforms: _ } => (),
+            message => self.occupancy.front(message.train_id()).map(|_| ()).ok_or(format!("unknown train {}", message.train_id())),
         }
+    }
 
-        self.propagate_signal(block_id, Owner::Train { id: train_id }, SignalColour::Red );
+    fn receive(&mut self) {
+        while let Ok(message) = self.rx.try_recv() {
+            self.inbox.push(message);
+        }
     }
 
-    fn next_in_path(&self, _train_id: &'a str, block_id: &'a str) -> &'a str { // signature needs changing back to train_id: &str only
-        return self.network.edges_directed(block_id, Outgoing).next().unwrap().1 // 1 because outgoing
+    // when the signalling is done from outside, everything the trains have said, in the order it would have been
+    // handled, keeping only the channels back to them
+    pub fn relay(&mut self) -> Vec<TrainMessage> {
+        self.receive();
+        let mut messages = std::mem::take(&mut self.inbox);
+        messages.sort_by_key(|message| message.train_id());
+        for message in &messages {
+            if let TrainMessage::HelloWorld { tx, train_id, .. } = message {
+                self.tx.insert(*train_id, tx.clone());
+            }
+        }
+        messages
     }
 
-    fn prev_in_path(&self, _train_id: &'a str, block_id: &'a str) -> &'a str { // signature needs changing back to train_id: &str only
-        return self.network.edges_directed(block_id,Incoming).next().unwrap().0 // 0 because incoming
+    pub fn deliver(&self, train_id: TrainId, message: SignallerMessage) -> Result<(), String> {
+        let tx = self.tx.get(&train_id).ok_or(format!("unknown train {train_id}"))?;
+        tx.send(message).map_err(|_| format!("{train_id} has gone"))
     }
 
-    pub fn propagate_signal(&self, block_id: &'a str, owner: Owner<'a>, colour: SignalColour) {
-        let prev_block_ids = self.network.neighbors_directed(block_id, Incoming);
-        
-        for prev_block_id in prev_block_ids {
-            let mut prev_block = self.network.edge_weight(prev_block_id, block_id).unwrap().lock().unwrap();
-            match &mut prev_block.block_type {
-                BlockType::Track { signal } => {
-                    if signal.update(owner, colour) {
-                        let next_colour = colour.next();
+    pub fn snapshot(&mut self) -> SignallerState {
+        self.receive();
+        let blocks = self.network.nodes().filter(|block_id| *block_id != DEAD_END)
+            .map(|block_id| (block_id, self.track(block_id).state()))
+            .collect();
+
+        SignallerState {
+            blocks,
+            occupancy: self.occupancy.positions(),
+            train_routes: self.train_routes.clone().into_iter().collect(),
+            train_lengths: self.train_lengths.clone().into_iter().collect(),
+            train_stops: self.train_stops.clone().into_iter().collect(),
+            routes: self.routes.clone().into_iter().collect(),
+            direction_locks: self.direction_locks.clone().into_iter().collect(),
+            waiting: self.waiting.clone(),
+            held_signals: self.held_signals.clone(),
+            deadlocked: self.deadlocked.clone(),
+            inbox: self.inbox.clone(),
+        }
+    }
 
-                        match self.train_positions.get(&prev_block_id, &"").0 {
-                            Some(train_id) => self.tx.get(train_id).unwrap().send(SignallerMessage::UpdateBlock { colour: next_colour, limit: prev_block.limit }).unwrap(),
-                            None => (),
-                        };
+    // pick up where a snapshot left off, keeping only the channels from the drivers' greetings
+    pub fn restore(&mut self, state: SignallerState) -> Result<(), String> {
+        for (block_id, block_state) in state.blocks {
+            if block_id == DEAD_END || !self.network.contains_node(block_id) {
+                return Err(format!("unknown block {block_id}"));
+            }
+            self.track(block_id).restore(block_state);
+        }
 
-                        self.propagate_signal(prev_block_id, owner, next_colour);
-                    }
+        self.receive();
+        for message in std::mem::take(&mut self.inbox) {
+            if let TrainMessage::HelloWorld { tx, train_id, .. } = message {
+                self.tx.insert(train_id, tx);
+            }
+        }
+        if let Some(train_id) = state.occupancy.keys().find(|train_id| !self.tx.contains_key(train_id)) {
+            return Err(format!("unknown train {train_id}"));
+        }
+
+        self.occupancy = Occupancy::from_positions(state.occupancy);
+        self.train_routes = state.train_routes.into_iter().collect();
+        self.train_lengths = state.train_lengths.into_iter().collect();
+        self.train_stops = state.train_stops.into_iter().collect();
+        self.routes = state.routes.into_iter().collect();
+        self.direction_locks = state.direction_locks.into_iter().collect();
+        self.waiting = state.waiting;
+        self.held_signals = state.held_signals;
+        self.deadlocked = state.deadlocked;
+        self.inbox = state.inbox;
+        self.events.take();
+        Ok(())
+    }
+
+    // automatic route setting's say in who goes first, acted on between ticks once every train has been heard from,
+    // routes ahead being set in that order before anything held is looked at again
+    pub fn regulate(&mut self, regulation: Regulation) {
+        self.regulation = regulation;
+        for train_id in self.regulation.order.clone() {
+            self.route_ahead(train_id);
+        }
+        self.retry_waiting();
+    }
+
+    // signalling by hand, refused wherever the interlocking would refuse it
+    pub fn command(&mut self, command: Command) -> Result<String, String> {
+        match command {
+            Command::Route { block_id, next_block_id } => self.manual_route(block_id, next_block_id),
+            Command::Cancel { block_id } => self.cancel_route(block_id),
+            Command::Danger { block_id } => {
+                self.check_block(block_id)?;
+                self.held_signals.insert(block_id);
+                self.set_signal(block_id, Owner::Signaller, SignalColour::Red);
+                Ok(format!("{block_id} held at danger"))
+            },
+            Command::Release { block_id } => {
+                self.check_block(block_id)?;
+                if !self.held_signals.remove(&block_id) {
+                    return Err(format!("{block_id} is not held"));
+                }
+                let colour = self.released_colour(block_id);
+                self.set_signal(block_id, Owner::Signaller, colour);
+                self.retry_waiting();
+                Ok(format!("{block_id} released"))
+            },
+        }
+    }
+
+    fn check_block(&self, block_id: BlockId) -> Result<(), String> {
+        if self.block(block_id).is_none() {
+            return Err(format!("unknown block {block_id}"));
+        }
+        Ok(())
+    }
+
+    // set a route for the train approaching a signal, which may be a different way than it would have been given
+    fn manual_route(&mut self, block_id: BlockId, next_block_id: BlockId) -> Result<String, String> {
+        self.check_block(block_id)?;
+        self.check_block(next_block_id)?;
+        if !self.network.contains_edge(block_id, next_block_id) {
+            return Err(format!("no route from {block_id} to {next_block_id}"));
+        }
+        let Some(train_id) = self.occupancy.fronts(block_id).next() else {
+            return Err(format!("no train at {block_id}"));
+        };
+        if let Some((routed_block_id, holder)) = self.routes.get(&block_id) {
+            return if (*routed_block_id, *holder) == (next_block_id, train_id) {
+                Ok(format!("route from {block_id} to {next_block_id} already set for {train_id}"))
+            }
+            else {
+                Err(format!("route from {block_id} to {routed_block_id} already set for {holder}"))
+            };
+        }
+        if self.train_routes.contains_key(&train_id) && !self.branches(train_id, block_id).contains(&next_block_id) {
+            return Err(format!("{train_id} is not booked through {next_block_id}"));
+        }
+        if self.would_deadlock(train_id, next_block_id) {
+            return Err(format!("route from {block_id} to {next_block_id} would leave {train_id} deadlocked"));
+        }
+        if !self.set_route(train_id, block_id, next_block_id) {
+            return Err(format!("route from {block_id} to {next_block_id} is locked"));
+        }
+
+        self.held_signals.remove(&block_id);
+        self.waiting.retain(|waiting| *waiting != (train_id, block_id, false));
+        self.clear_route(train_id, block_id, next_block_id);
+        Ok(format!("route set from {block_id} to {next_block_id} for {train_id}"))
+    }
+
+    // give back a route the train has not yet passed the signal for, and keep the signal at danger
+    // so it is not set again straight away
+    fn cancel_route(&mut self, block_id: BlockId) -> Result<String, String> {
+        self.check_block(block_id)?;
+        let Some((next_block_id, train_id)) = self.routes.get(&block_id).copied() else {
+            return Err(format!("no route set from {block_id}"));
+        };
+        // a route set ahead of the train can be taken back before it gets there too
+        let approaching = self.occupancy.front(train_id) == Some(block_id);
+        if !approaching && self.occupancy.trains(block_id).any(|id| id == train_id) {
+            return Err(format!("{train_id} has passed {block_id}"));
+        }
+
+        self.held_signals.insert(block_id);
+        self.release_route(train_id, block_id);
+        self.leave_platform(train_id, next_block_id);
+        self.release_lock(train_id, next_block_id);
+        self.set_signal(block_id, Owner::Signaller, SignalColour::Red);
+        self.events.borrow_mut().push(Event::RouteCancelled { train_id, block_id, next_block_id });
+        if approaching {
+            self.waiting.push((train_id, block_id, false));
+        }
+        self.retry_waiting();
+        Ok(format!("route from {block_id} to {next_block_id} cancelled, {train_id} held at {block_id}"))
+    }
+
+    // what a signal shows once it is no longer held, as if it had been left alone
+    fn released_colour(&self, block_id: BlockId) -> SignalColour {
+        if let Some((next_block_id, train_id)) = self.routes.get(&block_id) {
+            return self.signal_colour(*next_block_id, *train_id).next();
+        }
+        if self.is_controlled(block_id) {
+            return SignalColour::Red;
+        }
+        match self.network.neighbors_directed(block_id, Outgoing).next() {
+            Some(next_block_id) => {
+                // nothing in the block ahead or locked against it, in either direction
+                let reverse_block_id = self.track(next_block_id).reverse;
+                let blocked = self.occupancy.is_occupied(next_block_id) || reverse_block_id.is_some_and(|reverse_block_id| {
+                    self.occupancy.is_occupied(reverse_block_id) || self.direction_locks.contains_key(&reverse_block_id)
+                });
+                match &self.track(next_block_id).block_type {
+                    BlockType::Track { signal } if !blocked => signal.colour.next(),
+                    _ => SignalColour::Red,
+                }
+            },
+            None => SignalColour::Red, // buffer stops
+        }
+    }
+
+    fn reserve_block(&mut self, block_id: BlockId, next_block_id: BlockId, train_id: TrainId, reversed: bool) {
+        debug!("reserving block {} for {} from {}", next_block_id, train_id, block_id);
+        self.occupancy.enter(train_id, next_block_id);
+        if reversed {
+            // the whole train is taken to change ends within the block, so it leaves everything behind at once
+            for behind_block_id in self.occupancy.behind(train_id) {
+                self.vacate(train_id, behind_block_id);
+            }
+        }
+        self.enter_platform(train_id, next_block_id);
+
+        // a train calling here is given the road when it asks for it, not on arrival
+        let platform = self.platform_of(train_id, next_block_id).map(|index| index + 1);
+        self.events.borrow_mut().push(Event::BlockEntered { train_id, block_id: next_block_id, platform });
+
+        let calling = match self.train_stops.get_mut(&train_id) {
+            Some(stops) if stops.front().is_some_and(|(location, _)| *location == next_block_id) => {
+                stops.pop_front();
+                true
+            },
+            _ => false,
+        };
+
+        if let Some((route, index)) = self.train_routes.get_mut(&train_id) {
+            if route.get(*index + 1) == Some(&next_block_id) {
+                *index += 1;
+            }
+        }
+
+        let next_in_path = self.next_in_path(train_id);
+        let reversible = next_in_path.is_some_and(|ahead_block_id| self.is_reversal(next_block_id, ahead_block_id));
+
+        {
+            let next_block = &self.track(next_block_id);
+
+            match &next_block.block_type {
+                BlockType::Track { signal } => {
+                    self.tx.get(&train_id).unwrap().send(SignallerMessage::NewBlock { 
+                        new_block_id: next_block_id, 
+                        colour: signal.colour,
+                        limit: next_block.limit, 
+                        length: next_block.length,
+                        profile: next_block.profile.clone(),
+                        reversed,
+                        reversible,
+                    }).unwrap();
                 },
-                BlockType::Station { platforms: _ } => {
-                    todo!();
+                BlockType::Station { platforms } => {
+                    let colour = platforms.iter().find(|platform| platform.occupant == Some(train_id)).map_or(SignalColour::Red, |platform| platform.signal.colour);
+                    self.tx.get(&train_id).unwrap().send(SignallerMessage::NewBlock { 
+                        new_block_id: next_block_id, 
+                        colour,
+                        limit: next_block.limit, 
+                        length: next_block.length,
+                        profile: next_block.profile.clone(),
+                        reversed,
+                        reversible,
+                    }).unwrap();
                 },
             }
         }
+
+        self.protect_block(next_block_id, Owner::Train { id: train_id }); 
+        self.retry_waiting();
+
+        // set the route ahead as soon as the train is on approach to the signal
+        match next_in_path {
+            Some(_) if reversible => (),
+            Some(_) if calling => {
+                debug!("{} calling at {}", train_id, next_block_id);
+                self.hold(train_id, next_block_id);
+            },
+            Some(_) => self.request_route(train_id, next_block_id),
+            None => {
+                if self.network.neighbors_directed(next_block_id, Outgoing).next().is_some() {
+                    debug!("{} reached the end of its route at {}", train_id, next_block_id);
+                    self.hold(train_id, next_block_id);
+                }
+            },
+        }
+    }
+
+    fn next_in_path(&self, train_id: TrainId) -> Option<BlockId> {
+        let block_id = self.position(train_id);
+
+        if let Some((route, index)) = self.train_routes.get(&train_id) {
+            return route.get(index + 1).copied();
+        }
+
+        let block = self.track(block_id);
+        match &block.points {
+            Some(points) => Some(points.lies_to()),
+            None => {
+                match self.network.edges_directed(block_id, Outgoing).next() {
+                    Some(edge) => Some(edge.1), // 1 because outgoing
+                    None => block.reverse, // change ends at a terminus
+                }
+            },
+        }
+    }
+
+    fn prev_in_path(&self, train_id: TrainId) -> BlockId {
+        self.network.edges_directed(self.position(train_id), Incoming).next().unwrap().0 // 0 because incoming
+    }
+
+    fn position(&self, train_id: TrainId) -> BlockId {
+        self.occupancy.front(train_id).unwrap()
+    }
+
+    fn is_reversal(&self, block_id: BlockId, next_block_id: BlockId) -> bool {
+        self.track(block_id).reverse == Some(next_block_id)
+    }
+
+    // ways a route could be set from the end of a block for a train
+    fn branches(&self, train_id: TrainId, block_id: BlockId) -> Vec<BlockId> {
+        if let Some((route, index)) = self.train_routes.get(&train_id) {
+            let mut branches: Vec<BlockId> = route.get(index + 1).into_iter().copied().collect();
+
+            // a parallel line that rejoins the route straight after can be used instead
+            if let (Some(next_block_id), Some(after_block_id)) = (route.get(index + 1), route.get(index + 2)) {
+                for other_block_id in self.network.neighbors_directed(block_id, Outgoing) {
+                    if other_block_id != *next_block_id && self.network.contains_edge(other_block_id, *after_block_id) {
+                        branches.push(other_block_id);
+                    }
+                }
+            }
+
+            return branches;
+        }
+
+        // otherwise the way the points already lie first
+        match &self.track(block_id).points {
+            Some(points) => {
+                if points.lies_to() == points.normal { vec![points.normal, points.reverse] } else { vec![points.reverse, points.normal] }
+            },
+            None => self.network.neighbors_directed(block_id, Outgoing).collect(),
+        }
+    }
+
+    // none for the boundary or anything not in the network
+    fn block(&self, block_id: BlockId) -> Option<&Arc<Mutex<Block>>> {
+        self.network.edges_directed(block_id, Incoming).next().map(|edge| edge.2) // every incoming edge shares the block
+    }
+
+    // a block known to be in the network, as every id handed to the signaller is checked on the way in
+    fn track(&self, block_id: BlockId) -> MutexGuard<'_, Block> {
+        self.block(block_id).unwrap_or_else(|| panic!("{block_id} is not in the network")).lock().unwrap()
+    }
+
+    fn is_bidirectional(&self, block_id: BlockId) -> bool {
+        self.track(block_id).reverse.is_some()
+    }
+
+    fn is_station(&self, block_id: BlockId) -> bool {
+        matches!(self.track(block_id).block_type, BlockType::Station { .. })
+    }
+
+    // signals leading over points, into a station or out of one only clear when a route is set
+    fn is_controlled(&self, block_id: BlockId) -> bool {
+        let has_points = self.track(block_id).points.is_some();
+        has_points || self.is_station(block_id) ||
+        self.network.neighbors_directed(block_id, Outgoing).any(|next_block_id| {
+            let has_trailing_points = self.track(next_block_id).trailing_points.is_some();
+            has_trailing_points || self.is_station(next_block_id)
+        })
+    }
+
+    // a station is occupied while any of its platforms is, anything else while any part of a train is in it
+    fn is_occupied_by_other(&self, block_id: BlockId, train_id: TrainId) -> bool {
+        if let BlockType::Station { platforms } = &self.track(block_id).block_type {
+            return platforms.iter().any(|platform| platform.occupant.is_some_and(|occupant| occupant != train_id));
+        }
+        self.occupancy.is_occupied_by_other(block_id, train_id)
+    }
+
+    // the platform a train already has, otherwise the one booked in its timetable if it calls here next,
+    // otherwise the first free platform long enough for it
+    fn choose_platform(&self, train_id: TrainId, block_id: BlockId) -> Option<usize> {
+        let length = self.train_lengths.get(&train_id).copied().unwrap_or(0.0);
+        let booked = self.train_stops.get(&train_id)
+            .and_then(|stops| stops.front())
+            .filter(|(location, _)| *location == block_id)
+            .map(|(_, platform)| platform - 1); // platforms are numbered from 1
+
+        let block = self.track(block_id);
+        let BlockType::Station { platforms } = &block.block_type else {
+            return None;
+        };
+        let fits = |platform: &Platform| platform.occupant.is_none_or(|occupant| occupant == train_id) && platform.length as f32 >= length;
+
+        if let Some(index) = platforms.iter().position(|platform| platform.occupant == Some(train_id)) {
+            return Some(index);
+        }
+        match booked {
+            Some(index) => platforms.get(index).filter(|platform| fits(platform)).map(|_| index),
+            None => platforms.iter().position(fits),
+        }
+    }
+
+    // index of the platform a train has in a station
+    fn platform_of(&self, train_id: TrainId, block_id: BlockId) -> Option<usize> {
+        match &self.track(block_id).block_type {
+            BlockType::Track { signal: _ } => None,
+            BlockType::Station { platforms } => platforms.iter().position(|platform| platform.occupant == Some(train_id)),
+        }
+    }
+
+    // a train entering a station without a route (starting there or changing ends) takes any platform it can
+    fn enter_platform(&self, train_id: TrainId, block_id: BlockId) {
+        if !self.is_station(block_id) {
+            return;
+        }
+
+        let index = self.choose_platform(train_id, block_id);
+        if let BlockType::Station { platforms } = &mut self.track(block_id).block_type {
+            match index.or_else(|| platforms.iter().position(|platform| platform.occupant.is_none())) {
+                Some(index) => platforms[index].occupant = Some(train_id),
+                None => debug!("{} entered {} with no platform free", train_id, block_id),
+            }
+        }
+    }
+
+    fn leave_platform(&self, train_id: TrainId, block_id: BlockId) {
+        let Some(block) = self.block(block_id) else { // placed on an entry block
+            return;
+        };
+        if let BlockType::Station { platforms } = &mut block.lock().unwrap().block_type {
+            for platform in platforms.iter_mut().filter(|platform| platform.occupant == Some(train_id)) {
+                platform.occupant = None;
+            }
+        }
+    }
+
+    fn request_route(&mut self, train_id: TrainId, block_id: BlockId) {
+        if !self.try_route(train_id, block_id) {
+            debug!("{} held at {}", train_id, block_id);
+            self.hold(train_id, block_id);
+            self.waiting.push((train_id, block_id, false));
+        }
+    }
+
+    // try each way out of a block until a route can be set, then clear its signal
+    fn try_route(&mut self, train_id: TrainId, block_id: BlockId) -> bool {
+        if self.held_signals.contains(&block_id) {
+            return false;
+        }
+        for next_block_id in self.branches(train_id, block_id) {
+            if self.set_route(train_id, block_id, next_block_id) {
+                self.clear_route(train_id, block_id, next_block_id);
+                return true;
+            }
+        }
+        false
+    }
+
+    // the train takes the route it has been given, and the signal clears for it
+    fn clear_route(&mut self, train_id: TrainId, block_id: BlockId, next_block_id: BlockId) {
+        if let Some((route, index)) = self.train_routes.get_mut(&train_id) {
+            route[*index + 1] = next_block_id;
+        }
+        let colour = self.signal_colour(next_block_id, train_id).next();
+        self.set_signal(block_id, Owner::Signaller, colour);
+    }
+
+    // interlocking - a route is refused if its points are locked for another route, the block it leads into is 
+    // locked for an opposing movement, it leads from a controlled signal into an occupied block, it leads
+    // into a station with no platform free that the train fits, or deadlocks are being avoided and it would cause one
+    pub fn set_route(&mut self, train_id: TrainId, block_id: BlockId, next_block_id: BlockId) -> bool {
+        if self.routes.get(&block_id) == Some(&(next_block_id, train_id)) { // already set ahead of the train
+            return true;
+        }
+        if self.routes.get(&block_id).is_some_and(|(_, holder)| *holder != train_id) {
+            return false;
+        }
+        if self.track(block_id).points.is_some_and(|points| !points.can_set(next_block_id, train_id)) {
+            return false;
+        }
+        if self.track(next_block_id).trailing_points.is_some_and(|points| !points.can_set(block_id, train_id)) {
+            return false;
+        }
+        let platform = if self.is_station(next_block_id) {
+            match self.choose_platform(train_id, next_block_id) {
+                Some(index) => Some(index),
+                None => return false,
+            }
+        }
+        else {
+            None
+        };
+        if platform.is_none() && self.is_controlled(block_id) && self.is_occupied_by_other(next_block_id, train_id) {
+            return false;
+        }
+        if self.is_bidirectional(next_block_id) && !self.can_lock(train_id, next_block_id) {
+            return false;
+        }
+        if self.would_deadlock(train_id, next_block_id) {
+            debug!("route from {} to {} for {} refused to avoid a deadlock", block_id, next_block_id, train_id);
+            return false;
+        }
+
+        if let Some(points) = &mut self.track(block_id).points {
+            points.set_and_lock(next_block_id, train_id);
+        }
+        if let Some(points) = &mut self.track(next_block_id).trailing_points {
+            points.set_and_lock(block_id, train_id);
+        }
+        if self.is_bidirectional(next_block_id) {
+            self.lock_direction(train_id, next_block_id);
+        }
+        if let (Some(index), BlockType::Station { platforms }) = (platform, &mut self.track(next_block_id).block_type) {
+            platforms[index].occupant = Some(train_id);
+        }
+
+        debug!("route set from {} to {} for {}", block_id, next_block_id, train_id);
+        self.events.borrow_mut().push(Event::RouteSet { train_id, block_id, next_block_id });
+        self.routes.insert(block_id, (next_block_id, train_id));
+        true
+    }
+
+    fn try_reverse(&mut self, train_id: TrainId, block_id: BlockId) -> bool {
+        let reverse_block_id = self.track(block_id).reverse;
+        match reverse_block_id {
+            Some(reverse_block_id) => {
+                if !self.would_deadlock(train_id, reverse_block_id) && self.try_lock(train_id, reverse_block_id) {
+                    self.reserve_block(block_id, reverse_block_id, train_id, true);
+                    true
+                }
+                else {
+                    false
+                }
+            },
+            None => {
+                debug!("{} cannot reverse in {}", train_id, block_id);
+                true
+            },
+        }
+    }
+
+    // a bidirectional block can only be locked in one direction, by one train, while nothing stands on it
+    fn can_lock(&self, train_id: TrainId, block_id: BlockId) -> bool {
+        let reverse_block_id = self.track(block_id).reverse.unwrap();
+
+        [block_id, reverse_block_id].iter().all(|id| {
+            self.direction_locks.get(id).is_none_or(|holder| *holder == train_id) &&
+            !self.is_occupied_by_other(*id, train_id)
+        })
+    }
+
+    fn lock_direction(&mut self, train_id: TrainId, block_id: BlockId) {
+        if self.direction_locks.insert(block_id, train_id).is_none() {
+            let reverse_block_id = self.track(block_id).reverse.unwrap();
+            debug!("{} locked {} against {}", train_id, block_id, reverse_block_id);
+            self.protect_block(reverse_block_id, Owner::Signaller);
+        }
+    }
+
+    fn try_lock(&mut self, train_id: TrainId, block_id: BlockId) -> bool {
+        if self.can_lock(train_id, block_id) {
+            self.lock_direction(train_id, block_id);
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    // the rear of a train has left a block, so its platform is given up, the signal protecting it can step up
+    // and anything the train locked to run through it is released
+    fn vacate(&mut self, train_id: TrainId, block_id: BlockId) {
+        if block_id == DEAD_END {
+            return;
+        }
+        debug!("{} cleared {}", train_id, block_id);
+        self.occupancy.clear(train_id, block_id);
+        self.leave_platform(train_id, block_id);
+        self.events.borrow_mut().push(Event::BlockCleared { train_id, block_id });
+
+        if !self.is_occupied_by_other(block_id, train_id) {
+            for prev_block_id in self.preceding(block_id) {
+                let route_holder = self.routes.get(&prev_block_id).map(|(_, holder)| *holder);
+                if !self.is_controlled(prev_block_id) || route_holder.is_some() {
+                    let colour = self.signal_colour(block_id, route_holder.unwrap_or(train_id)).next();
+                    self.set_signal(prev_block_id, Owner::Train { id: train_id }, colour);
+                }
+            }
+        }
+
+        self.release_routes(train_id, block_id);
+    }
+
+    // once a train has left a block, the route it took out of it and any direction lock on it are given back
+    // and trains held behind it may be able to go, even if it held no route
+    fn release_routes(&mut self, train_id: TrainId, block_id: BlockId) {
+        self.release_route(train_id, block_id);
+        self.release_lock(train_id, block_id);
+        self.retry_waiting();
+    }
+
+    fn release_route(&mut self, train_id: TrainId, block_id: BlockId) {
+        let released_route = self.routes.get(&block_id).filter(|(_, holder)| *holder == train_id).map(|(next_block_id, _)| *next_block_id);
+
+        if let Some(next_block_id) = released_route {
+            debug!("{} released route from {} to {}", train_id, block_id, next_block_id);
+            self.routes.remove(&block_id);
+            if let Some(points) = &mut self.track(block_id).points {
+                points.release(train_id);
+            }
+            if let Some(points) = &mut self.track(next_block_id).trailing_points {
+                points.release(train_id);
+            }
+            if self.is_controlled(block_id) {
+                self.set_signal(block_id, Owner::Signaller, SignalColour::Red);
+            }
+        }
+    }
+
+    fn release_lock(&mut self, train_id: TrainId, block_id: BlockId) {
+        let released_lock = self.direction_locks.get(&block_id).is_some_and(|holder| *holder == train_id);
+
+        if released_lock {
+            debug!("{} released {}", train_id, block_id);
+            self.direction_locks.remove(&block_id);
+            let reverse_block_id = self.track(block_id).reverse.unwrap();
+            let colour = self.signal_colour(reverse_block_id, train_id).next();
+            for prev_block_id in self.preceding(reverse_block_id) {
+                if !self.is_controlled(prev_block_id) {
+                    self.set_signal(prev_block_id, Owner::Signaller, colour);
+                }
+            }
+        }
+    }
+
+    // held trains are let go in the order automatic route setting wants them, and any it doesn't know of after
+    // in the order they were held
+    fn retry_waiting(&mut self) {
+        let mut waiting = std::mem::take(&mut self.waiting);
+        let order = &self.regulation.order;
+        waiting.sort_by_key(|(train_id, _, _)| order.iter().position(|id| id == train_id).unwrap_or(order.len()));
+
+        for (train_id, block_id, reversing) in waiting {
+            let granted = if reversing {
+                self.try_reverse(train_id, block_id)
+            }
+            else {
+                self.try_route(train_id, block_id)
+            };
+
+            if !granted {
+                self.waiting.push((train_id, block_id, reversing));
+            }
+        }
+    }
+
+    // routes along a timetabled train's path beyond the signal it is approaching, as far as the look ahead goes,
+    // stopping short of where it next calls or changes ends, a held signal, another train, or a route refused.
+    // a train is only sent onto a line worked both ways ahead of time if it could be locked all the way to its
+    // next stop, so it is never left facing another train with nowhere for either to go
+    fn route_ahead(&mut self, train_id: TrainId) {
+        let (Some((route, index)), Some(front)) = (self.train_routes.get(&train_id), self.occupancy.front(train_id)) else {
+            return;
+        };
+        if route[*index] != front {
+            return;
+        }
+        let path: Vec<BlockId> = route[*index..].to_vec();
+        let next_stop = self.train_stops.get(&train_id).and_then(|stops| stops.front()).map(|(location, _)| *location);
+        let end = path.iter().position(|block_id| Some(*block_id) == next_stop).unwrap_or(path.len() - 1);
+        let in_the_way = |signaller: &Self, block_id: BlockId| !signaller.is_station(block_id) && signaller.occupancy.is_occupied_by_other(block_id, train_id);
+
+        for (step, window) in path.windows(3).enumerate().take(self.regulation.look_ahead) {
+            let (prev_block_id, block_id, next_block_id) = (window[0], window[1], window[2]);
+            if self.routes.get(&prev_block_id) != Some(&(block_id, train_id)) {
+                return;
+            }
+            if Some(block_id) == next_stop || self.is_reversal(block_id, next_block_id) || self.held_signals.contains(&block_id) {
+                return;
+            }
+            if in_the_way(self, block_id) || in_the_way(self, next_block_id) {
+                return;
+            }
+            if self.routes.get(&block_id) == Some(&(next_block_id, train_id)) {
+                continue;
+            }
+            if self.is_bidirectional(next_block_id) && !path[step + 2..=end].iter().all(|id| !self.is_bidirectional(*id) || self.can_lock(train_id, *id)) {
+                return;
+            }
+            if !self.set_route(train_id, block_id, next_block_id) {
+                return;
+            }
+            let colour = self.signal_colour(next_block_id, train_id).next();
+            self.set_signal(block_id, Owner::Signaller, colour);
+        }
+    }
+
+    // the trains in the way of a train's route from the end of a block into the next, with another train taken to
+    // have been let into a block as well if given
+    fn blockers(&self, train_id: TrainId, block_id: BlockId, next_block_id: BlockId, claim: Option<(TrainId, BlockId)>) -> BTreeSet<TrainId> {
+        let holds = |holder: &TrainId, block_id: BlockId| self.holds(*holder, block_id, claim);
+        let mut blockers = self.lock_blockers(next_block_id, claim);
+        blockers.extend(self.routes.get(&block_id).map(|(_, holder)| *holder).filter(|holder| holds(holder, block_id)));
+        if let Some(points) = self.track(block_id).points {
+            blockers.extend(points.locked_by.filter(|holder| holds(holder, block_id)));
+        }
+        if let Some(points) = self.track(next_block_id).trailing_points {
+            // locked for a route into the block, which is let go along with the route
+            blockers.extend(points.locked_by.filter(|holder| {
+                self.routes.iter().find(|(_, route)| **route == (next_block_id, *holder)).is_none_or(|(from_block_id, _)| holds(holder, *from_block_id))
+            }));
+        }
+
+        if self.is_station(next_block_id) {
+            if self.choose_platform(train_id, next_block_id).is_none() {
+                if let BlockType::Station { platforms } = &self.track(next_block_id).block_type {
+                    blockers.extend(platforms.iter().filter_map(|platform| platform.occupant).filter(|holder| holds(holder, next_block_id)));
+                }
+            }
+        }
+        else if self.is_controlled(block_id) {
+            blockers.extend(self.occupants(next_block_id, claim));
+        }
+
+        blockers.remove(&train_id);
+        blockers
+    }
+
+    // the trains standing on or holding a bidirectional block in either direction
+    fn lock_blockers(&self, block_id: BlockId, claim: Option<(TrainId, BlockId)>) -> BTreeSet<TrainId> {
+        let Some(reverse_block_id) = self.track(block_id).reverse else {
+            return BTreeSet::new();
+        };
+        [block_id, reverse_block_id].into_iter().flat_map(|id| {
+            let holder = self.direction_locks.get(&id).copied().filter(|holder| self.holds(*holder, id, claim));
+            self.occupants(id, claim).into_iter().chain(holder)
+        }).collect()
+    }
+
+    fn occupants(&self, block_id: BlockId, claim: Option<(TrainId, BlockId)>) -> Vec<TrainId> {
+        let claimed = claim.filter(|(_, claimed_block_id)| *claimed_block_id == block_id).map(|(holder, _)| holder);
+        self.occupancy.trains(block_id).filter(|holder| self.holds(*holder, block_id, claim)).chain(claimed).collect()
+    }
+
+    // a train gives up everything it has in a block once its rear clears it, which it does without going past the
+    // signal ahead of it, so only what it has from its front onwards can keep another train waiting. a train taken
+    // to have been let into a block is past everywhere it is now
+    fn holds(&self, train_id: TrainId, block_id: BlockId, claim: Option<(TrainId, BlockId)>) -> bool {
+        let moving_on = claim.is_some_and(|(holder, _)| holder == train_id) && self.occupancy.front(train_id) == Some(block_id);
+        !moving_on && !self.occupancy.behind(train_id).contains(&block_id)
+    }
+
+    // every held train and, for each way it could go, the trains in the way of it
+    fn waits(&self, claim: Option<(TrainId, BlockId)>) -> BTreeMap<TrainId, Vec<BTreeSet<TrainId>>> {
+        self.waiting.iter().filter(|(train_id, _, _)| claim.is_none_or(|(holder, _)| holder != *train_id)).map(|(train_id, block_id, reversing)| {
+            let ways = if *reversing {
+                self.track(*block_id).reverse.map(|reverse_block_id| {
+                    let mut blockers = self.lock_blockers(reverse_block_id, claim);
+                    blockers.remove(train_id);
+                    blockers
+                }).into_iter().collect()
+            }
+            else {
+                self.branches(*train_id, *block_id).into_iter().map(|next_block_id| self.blockers(*train_id, *block_id, next_block_id, claim)).collect()
+            };
+            (*train_id, ways)
+        }).collect()
+    }
+
+    // where a train would want to go once it has been let into a block
+    fn ways_on(&self, train_id: TrainId, block_id: BlockId) -> Vec<BlockId> {
+        if let Some((route, index)) = self.train_routes.get(&train_id) {
+            return match route[*index..].iter().position(|id| *id == block_id) {
+                Some(offset) => route.get(index + offset + 1).copied().into_iter().collect(),
+                None => route.get(index + 2).copied().into_iter().collect(), // a parallel line, which rejoins the route straight after
+            };
+        }
+        let next: Vec<BlockId> = self.network.neighbors_directed(block_id, Outgoing).collect();
+        if next.is_empty() { self.track(block_id).reverse.into_iter().collect() } else { next }
     }
+
+    // letting a train into a block would leave it and others waiting on each other for good, if whatever it would
+    // want next is held by trains that are already waiting, however long, on the block it is let into
+    fn would_deadlock(&self, train_id: TrainId, next_block_id: BlockId) -> bool {
+        if !self.avoid_deadlocks {
+            return false;
+        }
+        let claim = Some((train_id, next_block_id));
+        let mut waits = self.waits(claim);
+        let ways = self.ways_on(train_id, next_block_id).into_iter().map(|after_block_id| {
+            if self.is_reversal(next_block_id, after_block_id) {
+                let mut blockers = self.lock_blockers(after_block_id, claim);
+                blockers.remove(&train_id);
+                blockers
+            }
+            else {
+                self.blockers(train_id, next_block_id, after_block_id, claim)
+            }
+        }).collect::<Vec<_>>();
+        if ways.is_empty() {
+            return false;
+        }
+        waits.insert(train_id, ways);
+        stuck(&waits).contains(&train_id)
+    }
+
+    // report trains left waiting on each other for good, once each time the set of them changes
+    fn detect_deadlock(&mut self) {
+        let stuck = stuck(&self.waits(None));
+        if !stuck.is_empty() && stuck != self.deadlocked {
+            let held: Vec<(TrainId, BlockId)> = stuck.iter().filter_map(|train_id| {
+                self.waiting.iter().find(|(id, _, _)| id == train_id).map(|(_, block_id, _)| (*train_id, *block_id))
+            }).collect();
+            warn!("deadlock between {:?}", held);
+            self.events.borrow_mut().push(Event::Deadlock {
+                train_ids: held.iter().map(|(train_id, _)| *train_id).collect(),
+                block_ids: held.iter().map(|(_, block_id)| *block_id).collect(),
+            });
+        }
+        self.deadlocked = stuck;
+    }
+
+    // blocks leading into a block, ignoring the boundary and any whose points are set elsewhere
+    fn preceding(&self, block_id: BlockId) -> Vec<BlockId> {
+        self.network.neighbors_directed(block_id, Incoming).filter(|id| {
+            self.network.neighbors_directed(*id, Incoming).next().is_some() &&
+            self.track(*id).points.is_none_or(|points| points.lies_to() == block_id)
+        }).collect()
+    }
+
+    // in a station, the signal at the end of the platform the train has
+    fn signal_colour(&self, block_id: BlockId, train_id: TrainId) -> SignalColour {
+        match &self.track(block_id).block_type {
+            BlockType::Track { signal } => signal.colour,
+            BlockType::Station { platforms } => {
+                platforms.iter().find(|platform| platform.occupant == Some(train_id)).map_or(SignalColour::Red, |platform| platform.signal.colour)
+            },
+        }
+    }
+
+    // set the signals protecting a block to danger
+    fn protect_block(&self, block_id: BlockId, owner: Owner) {
+        for prev_block_id in self.preceding(block_id) {
+            self.set_signal(prev_block_id, owner, SignalColour::Red);
+        }
+    }
+
+    // update the signal at the end of a block and tell any train approaching it
+    fn set_signal(&self, block_id: BlockId, owner: Owner, colour: SignalColour) {
+        self.propagate_signal(block_id, owner, colour);
+        self.notify(block_id);
+    }
+
+    // stop a train at the signal it is approaching, leaving any other platforms alone
+    fn hold(&self, train_id: TrainId, block_id: BlockId) {
+        if !self.is_station(block_id) {
+            self.propagate_signal(block_id, Owner::Signaller, SignalColour::Red);
+        }
+        else if let BlockType::Station { platforms } = &mut self.track(block_id).block_type {
+            for (index, platform) in platforms.iter_mut().enumerate().filter(|(_, platform)| platform.occupant == Some(train_id)) {
+                self.update_signal(block_id, Some(index), &mut platform.signal, Owner::Signaller, SignalColour::Red);
+            }
+        }
+        self.notify(block_id);
+    }
+
+    // tell every train in a block what its signal shows
+    fn notify(&self, block_id: BlockId) {
+        let block = self.track(block_id);
+        let updates: Vec<(TrainId, SignalColour)> = match &block.block_type {
+            BlockType::Track { signal } => {
+                self.occupancy.fronts(block_id).map(|train_id| (train_id, signal.colour)).collect()
+            },
+            BlockType::Station { platforms } => {
+                // a platform is taken as soon as a route is set into it, before the train arrives
+                platforms.iter()
+                    .filter_map(|platform| platform.occupant.map(|train_id| (train_id, platform.signal.colour)))
+                    .filter(|(train_id, _)| self.occupancy.front(*train_id) == Some(block_id))
+                    .collect()
+            },
+        };
+
+        for (train_id, colour) in updates {
+            self.tx.get(&train_id).unwrap().send(SignallerMessage::UpdateBlock { colour, limit: block.limit }).unwrap();
+        }
+    }
+
+    // change a signal and record it if the aspect shown changed
+    fn update_signal(&self, block_id: BlockId, platform: Option<usize>, signal: &mut Signal, owner: Owner, colour: SignalColour) -> bool {
+        let before = signal.colour;
+        let updated = signal.update(owner, colour);
+        if signal.colour != before {
+            self.events.borrow_mut().push(Event::SignalChanged { signal_id: SignalId::new(block_id, platform.map(|index| index + 1)), colour: signal.colour, owner: owner_name(signal.owner) });
+        }
+        updated
+    }
+
+    pub fn propagate_signal(&self, block_id: BlockId, owner: Owner, colour: SignalColour) {
+        // a signal held by hand stays at danger for the signaller whatever it is asked to show,
+        // and the signals behind step down to it as usual
+        let (owner, colour) = if self.held_signals.contains(&block_id) { (Owner::Signaller, SignalColour::Red) } else { (owner, colour) };
+        let updated = match &mut self.track(block_id).block_type {
+            BlockType::Track { signal } => {
+                self.update_signal(block_id, None, signal, owner, colour)
+            },
+            BlockType::Station { platforms } => {
+                // danger protects the line ahead of every platform, anything else only clears the platform the route is set from
+                let route_holder = self.routes.get(&block_id).map(|(_, holder)| *holder);
+                let mut updated = false;
+                for (index, platform) in platforms.iter_mut().enumerate() {
+                    if colour == SignalColour::Red || (platform.occupant.is_some() && platform.occupant == route_holder) {
+                        updated |= self.update_signal(block_id, Some(index), &mut platform.signal, owner, colour);
+                    }
+                }
+                updated
+            },
+        };
+
+        // lock is dropped before stepping back so loops and junctions can revisit a block
+        // signals in front of points stay at danger unless a route has been set from them,
+        // and signals protecting a block a train's body is still in stay at danger until it clears
+        if updated && !self.occupancy.is_occupied(block_id) {
+            for prev_block_id in self.preceding(block_id) {
+                if !self.is_controlled(prev_block_id) || self.routes.contains_key(&prev_block_id) {
+                    self.set_signal(prev_block_id, owner, colour.next());
+                }
+            }
+        }
+    }
+}
+
+// the held trains that can never go, given for each the trains in the way of every way it could go. a train can go
+// once everything in the way of any one of its ways can, so whatever is left when no more can go is stuck for good
+fn stuck(waits: &BTreeMap<TrainId, Vec<BTreeSet<TrainId>>>) -> BTreeSet<TrainId> {
+    let mut stuck: BTreeSet<TrainId> = waits.keys().copied().collect();
+    loop {
+        let free: Vec<TrainId> = stuck.iter().filter(|train_id| {
+            let ways = &waits[*train_id];
+            ways.is_empty() || ways.iter().any(|blockers| blockers.is_disjoint(&stuck))
+        }).copied().collect();
+        if free.is_empty() {
+            return stuck;
+        }
+        for train_id in free {
+            stuck.remove(&train_id);
+        }
+    }
+}
+
+impl Agent for Signaller {
+    fn step(&mut self) {
+        self.update();
+    }
+}
+
+#[test]
+fn test_interlocking() {
+    let (_, network) = crate::utils::io::passing_loop();
+    let (_, rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut signaller = Signaller::new(rx, network);
+
+    assert!(signaller.set_route("1A01".into(), "E1".into(), "LE".into()));
+    assert!(!signaller.set_route("3C03".into(), "E1".into(), "ME".into())); // facing points locked for 1A01
+    assert!(!signaller.set_route("2B02".into(), "W2".into(), "LW".into())); // opposing 1A01 in the loop
+    assert!(signaller.set_route("2B02".into(), "W2".into(), "MW".into()));
+
+    assert!(signaller.set_route("1A01".into(), "LE".into(), "E2".into()));
+    assert!(!signaller.set_route("3C03".into(), "ME".into(), "E2".into())); // trailing points locked for 1A01
+    assert_eq!(signaller.signal_colour("ME".into(), "3C03".into()), SignalColour::Red);
+}
+
+#[test]
+fn test_platforms() {
+    let scenario = crate::utils::io::load_scenario(concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios/station.yaml")).unwrap();
+    let (_, rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut signaller = Signaller::new(rx, crate::utils::io::build_network(&scenario.network).unwrap());
+
+    for train_id in ["1A01".into(), "2B02".into(), "3C03".into(), "4D04".into()] {
+        signaller.train_lengths.insert(train_id, 130.0);
+    }
+    signaller.train_stops.insert("1A01".into(), VecDeque::from([("S".into(), 2)]));
+    signaller.train_stops.insert("3C03".into(), VecDeque::from([("S".into(), 3)]));
+
+    assert!(!signaller.set_route("3C03".into(), "A".into(), "S".into())); // booked platform is too short
+    assert!(signaller.set_route("1A01".into(), "A".into(), "S".into()));
+    assert_eq!(signaller.choose_platform("1A01".into(), "S".into()), Some(1));
+    assert_eq!(signaller.signal_colour("S".into(), "1A01".into()), SignalColour::Red);
+
+    signaller.release_routes("1A01".into(), "A".into());
+    assert!(signaller.set_route("2B02".into(), "A".into(), "S".into()));
+    assert_eq!(signaller.choose_platform("2B02".into(), "S".into()), Some(0));
+
+    signaller.release_routes("2B02".into(), "A".into());
+    assert!(!signaller.set_route("4D04".into(), "A".into(), "S".into())); // only the short platform is left
+}
+
+#[test]
+fn test_tail_clearance() {
+    let (_, network) = crate::utils::io::passing_loop();
+    let (train_tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut signaller = Signaller::new(rx, network);
+    let (tx, _driver_rx) = tokio::sync::mpsc::unbounded_channel();
+
+    train_tx.send(TrainMessage::HelloWorld { tx, train_id: "1A01".into(), block_id: "W".into(), route: Vec::new(), length: 130.0, stops: Vec::new() }).unwrap();
+    train_tx.send(TrainMessage::ReserveNextBlock { train_id: "1A01".into() }).unwrap();
+    signaller.update();
+
+    // the front is in E1 but the rear is still in W, so W stays locked against the other direction
+    assert_eq!(signaller.position("1A01".into()), "E1");
+    assert_eq!(signaller.occupancy.behind("1A01".into()), vec!["W"]);
+    assert!(!signaller.can_lock("2B02".into(), "Wr".into()));
+
+    // a block the train isn't in, or that doesn't exist, is ignored
+    train_tx.send(TrainMessage::TailCleared { train_id: "1A01".into(), block_id: "NOPE".into() }).unwrap();
+    train_tx.send(TrainMessage::TailCleared { train_id: "1A01".into(), block_id: "LE".into() }).unwrap();
+    signaller.update();
+    assert_eq!(signaller.occupancy.behind("1A01".into()), vec!["W"]);
+
+    train_tx.send(TrainMessage::TailCleared { train_id: "1A01".into(), block_id: "W".into() }).unwrap();
+    signaller.update();
+    assert!(!signaller.occupancy.is_occupied("W".into()));
+    assert!(signaller.can_lock("2B02".into(), "Wr".into()));
+}
+
+#[test]
+fn test_manual_control() {
+    let (_, network) = crate::utils::io::passing_loop();
+    let (train_tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut signaller = Signaller::new(rx, network);
+    let (tx, _driver_rx) = tokio::sync::mpsc::unbounded_channel();
+    let train_id: TrainId = "1A01".into();
+
+    train_tx.send(TrainMessage::HelloWorld { tx, train_id, block_id: "W".into(), route: Vec::new(), length: 130.0, stops: Vec::new() }).unwrap();
+    signaller.update();
+
+    assert!(signaller.command(Command::Danger { block_id: "W".into() }).is_ok());
+    assert_eq!(signaller.signal_colour("W".into(), train_id), SignalColour::Red);
+    assert!(signaller.command(Command::Release { block_id: "W".into() }).is_ok());
+    assert_eq!(signaller.signal_colour("W".into(), train_id), SignalColour::Yellow); // E1 is at danger in front of the points
+
+    // the route into the loop is taken back and the train sent along the main line instead
+    train_tx.send(TrainMessage::ReserveNextBlock { train_id }).unwrap();
+    signaller.update();
+    assert_eq!(signaller.routes.get(&"E1".into()), Some(&("LE".into(), train_id)));
+    assert!(signaller.command(Command::Cancel { block_id: "W".into() }).is_err()); // already passed
+    assert!(signaller.command(Command::Cancel { block_id: "E1".into() }).is_ok());
+    assert_eq!(signaller.signal_colour("E1".into(), train_id), SignalColour::Red);
+    assert!(signaller.waiting.contains(&(train_id, "E1".into(), false)));
+
+    assert!(signaller.command(Command::Route { block_id: "W".into(), next_block_id: "E1".into() }).is_err()); // no train at W
+    assert!(signaller.command(Command::Route { block_id: "E1".into(), next_block_id: "ME".into() }).is_ok());
+    assert_eq!(signaller.next_in_path(train_id), Some("ME".into()));
+    assert_eq!(signaller.signal_colour("E1".into(), train_id), SignalColour::Yellow);
+    assert!(signaller.waiting.is_empty());
+}
+
+#[test]
+fn test_route_ahead() {
+    let (_, network) = crate::utils::io::passing_loop();
+    let (train_tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut signaller = Signaller::new(rx, network);
+    let (tx, _driver_rx) = tokio::sync::mpsc::unbounded_channel();
+    let train_id: TrainId = "1A01".into();
+    let route = ["W", "E1", "LE", "E2", "E"].map(BlockId::from).to_vec();
+
+    train_tx.send(TrainMessage::HelloWorld { tx, train_id, block_id: "W".into(), route, length: 130.0, stops: vec![("E".into(), 1)] }).unwrap();
+    signaller.update();
+    assert!(!signaller.routes.contains_key(&"E1".into()));
+
+    // over the points and into the loop, which is then locked against the other direction, but not beyond
+    signaller.regulate(Regulation { order: vec![train_id], look_ahead: 1 });
+    assert_eq!(signaller.routes.get(&"E1".into()), Some(&("LE".into(), train_id)));
+    assert!(!signaller.routes.contains_key(&"LE".into()));
+    assert!(!signaller.can_lock("2B02".into(), "LW".into()));
+    assert_ne!(signaller.signal_colour("E1".into(), train_id), SignalColour::Red);
+
+    // a route taken back ahead of the train leaves it where it is
+    assert!(signaller.command(Command::Cancel { block_id: "E1".into() }).is_ok());
+    assert!(signaller.waiting.is_empty());
+    assert!(signaller.command(Command::Release { block_id: "E1".into() }).is_ok());
+
+    // up to the station it calls at, and no further
+    signaller.regulate(Regulation { order: vec![train_id], look_ahead: 5 });
+    assert_eq!(signaller.routes.get(&"LE".into()), Some(&("E2".into(), train_id)));
+    assert_eq!(signaller.routes.get(&"E2".into()), Some(&("E".into(), train_id)));
+    assert!(!signaller.routes.contains_key(&"E".into()));
+}
+
+#[test]
+fn test_deadlock() {
+    let place = |avoid_deadlocks: bool, hold_2b02: bool| {
+        let (_, network) = crate::utils::io::passing_loop();
+        let (train_tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut signaller = Signaller::new(rx, network);
+        if avoid_deadlocks {
+            signaller.avoid_deadlocks();
+        }
+        let (tx, driver_rx) = tokio::sync::mpsc::unbounded_channel();
+        let hello = |train_id: &str, route: &[&str]| TrainMessage::HelloWorld {
+            tx: tx.clone(), train_id: train_id.into(), block_id: route[0].into(), route: route.iter().map(|id| BlockId::new(id)).collect(), length: 130.0, stops: Vec::new(),
+        };
+
+        // 1A01 in the loop with the line ahead set for it, and 2B02 at the far end of it facing the other way
+        let (first, second) = (hello("1A01", &["LE", "E2", "E"]), hello("2B02", &["Er", "W2", "MW", "W1", "Wr"]));
+        if hold_2b02 {
+            signaller.command(Command::Danger { block_id: "Er".into() }).unwrap();
+            train_tx.send(second).unwrap();
+            signaller.update();
+            train_tx.send(first).unwrap();
+        }
+        else {
+            train_tx.send(first).unwrap();
+            signaller.update();
+            train_tx.send(second).unwrap();
+        }
+        signaller.update();
+        (signaller, train_tx, driver_rx)
+    };
+
+    // each is left wanting the block the other holds, which is reported once
+    let (mut signaller, train_tx, _driver_rx) = place(false, false);
+    train_tx.send(TrainMessage::ReserveNextBlock { train_id: "1A01".into() }).unwrap();
+    signaller.update();
+    signaller.update();
+    let deadlocks: Vec<Event> = signaller.take_events().into_iter().filter(|event| matches!(event, Event::Deadlock { .. })).collect();
+    assert_eq!(deadlocks, vec![Event::Deadlock { train_ids: vec!["1A01".into(), "2B02".into()], block_ids: vec!["E2".into(), "Er".into()] }]);
+
+    // with 2B02 already waiting at Er, 1A01 is kept in the loop instead until 2B02 has passed it
+    let (mut signaller, _train_tx, _driver_rx) = place(true, true);
+    assert!(!signaller.routes.contains_key(&"LE".into()));
+    assert!(signaller.waiting.contains(&("1A01".into(), "LE".into(), false)));
+    assert!(signaller.command(Command::Route { block_id: "LE".into(), next_block_id: "E2".into() }).is_err());
+    assert!(signaller.command(Command::Release { block_id: "Er".into() }).is_ok());
+    assert_eq!(signaller.routes.get(&"Er".into()), Some(&("W2".into(), "2B02".into())));
+    assert!(signaller.take_events().iter().all(|event| !matches!(event, Event::Deadlock { .. })));
 }
@@ -1,48 +1,83 @@
 use crate::{
     infrastructure::{
-        signal::Signal, platform::Platform
+        signal::Signal, platform::Platform, points::Points
     },
     utils::{
-        conversion::convert_to_mps
+        conversion::convert_to_mps, id::BlockId, surface::Surface
     }
 };
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
-pub enum BlockType <'a> {
-    Track { signal: Signal<'a> },
-    Station { platforms: Vec<Platform<'a>> }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BlockType {
+    Track { signal: Signal },
+    Station { platforms: Vec<Platform> }
 }
 
 #[derive(Debug)]
-pub struct Block <'a> {
+pub struct Block {
     pub length: u32,
     pub limit: f32,
-    pub block_type: BlockType<'a>,
+    pub reverse: Option<BlockId>, // same physical track worked in the opposite direction
+    pub points: Option<Points>, // facing points at the exit, choosing the next block
+    pub trailing_points: Option<Points>, // trailing points at the entrance, choosing the previous block
+    pub profile: Vec<Surface>, // gradients and curves along the block in the direction of travel
+    pub block_type: BlockType,
+}
+
+// the parts of a block that change as trains run over it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockState {
+    pub points: Option<Points>,
+    pub trailing_points: Option<Points>,
+    pub block_type: BlockType,
 }
 
 
-impl <'a> Block <'a> {
-    pub fn new_track(length: u32, limit: f32, signal: Signal<'a>,) -> Self {
+impl Block {
+    pub fn new_track(length: u32, limit: f32, signal: Signal,) -> Self {
         Block {
             length,
             limit: convert_to_mps(limit),
+            reverse: None,
+            points: None,
+            trailing_points: None,
+            profile: Vec::new(),
             block_type: BlockType::Track { 
                 signal
             }
         }
     }
 
-    pub fn new_station(length: u32, limit: f32, platforms: Vec<Platform<'a>>) -> Self {
+    pub fn new_station(length: u32, limit: f32, platforms: Vec<Platform>) -> Self {
         Block {
             length,
             limit: convert_to_mps(limit),
+            reverse: None,
+            points: None,
+            trailing_points: None,
+            profile: Vec::new(),
             block_type: BlockType::Station {
                 platforms,
             }
         }
     }
 
-    pub fn add_platform(&mut self, platform: Platform<'a>) {
+    pub fn state(&self) -> BlockState {
+        BlockState {
+            points: self.points,
+            trailing_points: self.trailing_points,
+            block_type: self.block_type.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: BlockState) {
+        self.points = state.points;
+        self.trailing_points = state.trailing_points;
+        self.block_type = state.block_type;
+    }
+
+    pub fn add_platform(&mut self, platform: Platform) {
         match &mut self.block_type {
             BlockType::Track { signal: _ } => {
                 panic!("CANNOT ADD PLATFORM TO TRACK");
@@ -1,14 +1,15 @@
-use crate::infrastructure::signal::Signal;
+use crate::{infrastructure::signal::Signal, utils::id::TrainId};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
-pub struct Platform<'a> {
-    pub signal: Signal<'a>,
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
+pub struct Platform {
+    pub signal: Signal,
     pub length: u32,
-    pub occupant: Option<&'a str>
+    pub occupant: Option<TrainId>
 }
 
-impl <'a> Platform<'a> {
-    pub fn new(signal: Signal<'a>, length: u32) -> Self {
+impl Platform {
+    pub fn new(signal: Signal, length: u32) -> Self {
         Platform {
             signal,
             length,
@@ -0,0 +1,84 @@
+use crate::utils::id::{BlockId, TrainId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash, Serialize, Deserialize)]
+pub enum PointsPosition {
+    Normal,
+    Reverse
+}
+
+// a two way switch between one block and either of two others
+// facing points choose the next block, trailing points choose which block is let in
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash, Serialize, Deserialize)]
+pub struct Points {
+    pub normal: BlockId,
+    pub reverse: BlockId,
+    pub position: PointsPosition,
+    pub locked_by: Option<TrainId>,
+}
+
+impl Points {
+    pub fn new(normal: BlockId, reverse: BlockId) -> Self {
+        Points {
+            normal,
+            reverse,
+            position: PointsPosition::Normal,
+            locked_by: None,
+        }
+    }
+
+    pub fn lies_to(&self) -> BlockId {
+        match self.position {
+            PointsPosition::Normal => self.normal,
+            PointsPosition::Reverse => self.reverse,
+        }
+    }
+
+    pub fn position_for(&self, block_id: BlockId) -> Option<PointsPosition> {
+        if block_id == self.normal {
+            Some(PointsPosition::Normal)
+        }
+        else if block_id == self.reverse {
+            Some(PointsPosition::Reverse)
+        }
+        else {
+            None
+        }
+    }
+
+    // points locked for another train's route can't be moved or shared
+    pub fn can_set(&self, block_id: BlockId, train_id: TrainId) -> bool {
+        self.position_for(block_id).is_some() && self.locked_by.is_none_or(|holder| holder == train_id)
+    }
+
+    pub fn set_and_lock(&mut self, block_id: BlockId, train_id: TrainId) -> bool {
+        if !self.can_set(block_id, train_id) {
+            return false;
+        }
+        self.position = self.position_for(block_id).unwrap();
+        self.locked_by = Some(train_id);
+        true
+    }
+
+    pub fn release(&mut self, train_id: TrainId) {
+        if self.locked_by == Some(train_id) {
+            self.locked_by = None;
+        }
+    }
+}
+
+#[test]
+fn test_set_and_lock() {
+    let mut points = Points::new("A".into(), "B".into());
+    assert_eq!(points.lies_to(), "A");
+
+    assert!(points.set_and_lock("B".into(), "1A01".into()));
+    assert_eq!(points.lies_to(), "B");
+    assert!(!points.set_and_lock("A".into(), "2B02".into()));
+    assert!(!points.set_and_lock("B".into(), "2B02".into()));
+
+    points.release("1A01".into());
+    assert!(points.set_and_lock("A".into(), "2B02".into()));
+    assert_eq!(points.position, PointsPosition::Normal);
+    assert!(!points.set_and_lock("C".into(), "2B02".into()));
+}
@@ -1,6 +1,9 @@
-use std::{hash::{Hash, Hasher}};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord)]
+use crate::utils::id::TrainId;
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SignalColour {
     Red,
     Yellow,
@@ -20,19 +23,19 @@ impl SignalColour {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
-pub enum Owner <'a> {
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash, Serialize, Deserialize)]
+pub enum Owner {
     Signaller,
-    Train {id: &'a str}
+    Train {id: TrainId}
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
-pub struct Signal<'a> {
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash, Serialize, Deserialize)]
+pub struct Signal {
     pub colour: SignalColour,
-    pub owner: Owner<'a>,
+    pub owner: Owner,
 }
 
-impl<'a> Signal <'a> {
+impl Signal {
     pub fn new() -> Self {
         Signal {
             colour: SignalColour::Green,
@@ -40,7 +43,7 @@ impl<'a> Signal <'a> {
         }
     }
 
-    pub fn update(&mut self, owner: Owner <'a>, colour: SignalColour) -> bool {
+    pub fn update(&mut self, owner: Owner, colour: SignalColour) -> bool {
         match self.owner {
             Owner::Signaller => {
                 match owner {
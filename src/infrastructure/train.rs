@@ -1,45 +1,121 @@
-use crate::{utils::conversion::convert_to_mph, GRAVITY};
+use crate::utils::{conversion::convert_to_mph, id::TrainId, surface::{Surface, surface_at}};
 use log::debug;
+use serde::{Deserialize, Serialize};
 
-#[macro_export]
-macro_rules! class802 {
-    ($name:expr) => {
-        Train::new($name, 300000.0, 700000, 3, 2.5, 3.5, 1.0)
-    };
+// resistance to motion of the whole train, a + bv + cv^2 newtons at v m/s
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Davis {
+    pub a: f32, // bearing and rolling resistance, N
+    pub b: f32, // flange and other speed dependent resistance, N per m/s
+    pub c: f32, // air resistance, N per (m/s)^2
 }
 
-#[derive(Debug)]
-pub struct Train <'a> {
-    pub name: &'a str,
+// force available against speed, as [speed m/s, force N] points in order of speed
+// read off linearly in between and held level beyond either end
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<(f32, f32)>", into = "Vec<(f32, f32)>")]
+pub struct EffortCurve(Vec<(f32, f32)>);
+
+// a curve given at all needs a point, and its speeds rising throughout for reading off between them
+impl TryFrom<Vec<(f32, f32)>> for EffortCurve {
+    type Error = String;
+
+    fn try_from(points: Vec<(f32, f32)>) -> Result<Self, Self::Error> {
+        if points.is_empty() {
+            return Err("effort curve has no points".to_string());
+        }
+        if let Some(pair) = points.windows(2).find(|pair| pair[0].0.partial_cmp(&pair[1].0) != Some(std::cmp::Ordering::Less)) {
+            return Err(format!("effort curve speeds must rise throughout, {} is followed by {}", pair[0].0, pair[1].0));
+        }
+        Ok(EffortCurve(points))
+    }
+}
+
+impl From<EffortCurve> for Vec<(f32, f32)> {
+    fn from(curve: EffortCurve) -> Self {
+        curve.0
+    }
+}
+
+impl EffortCurve {
+    pub fn at(&self, speed: f32) -> Option<f32> {
+        let (first, last) = (self.0.first()?, self.0.last()?);
+        if speed <= first.0 {
+            return Some(first.1);
+        }
+        let Some(upper) = self.0.iter().position(|(point_speed, _)| *point_speed >= speed) else {
+            return Some(last.1);
+        };
+        let ((speed_0, force_0), (speed_1, force_1)) = (self.0[upper - 1], self.0[upper]);
+        Some(force_0 + (force_1 - force_0) * (speed - speed_0) / (speed_1 - speed_0))
+    }
+}
+
+// a class of train as listed in the rolling stock catalogue, everything in SI units
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RollingStock {
+    pub class: String,
+    pub mass: f32, // kg
+    pub length: f32, // m
+    pub power: f32, // W at the rail
+    pub max_speed: f32, // m/s
+    pub max_tractive_effort: f32, // N, available from a stand until power runs out
+    pub service_brake: f32, // m/s^2 at full service braking
+    pub emergency_brake: f32, // m/s^2
+    #[serde(default)]
+    pub rotary_allowance: f32, // share of mass added to account for wheels, axles and motors that also need spinning up
+    #[serde(default)]
+    pub tractive_effort: EffortCurve, // without one, max_tractive_effort until power runs out
+    #[serde(default)]
+    pub braking_effort: EffortCurve, // without one, service_brake at every speed
+    pub davis: Davis,
+}
+
+const STOPPING_BRAKE: f32 = 0.6; // share of full service braking a train is brought to a stand with
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Train {
+    pub name: TrainId,
+    pub length: f32,
+    pub max_speed: f32,
     mass: f32,
-    power: u32,
-    axle_resistance: f32,
-    rolling_resistance: f32,
-    air_resistance_coefficient: f32,
-    max_tractive_effort: u32,
+    power: f32,
+    max_tractive_effort: f32,
+    service_deceleration: f32,
+    emergency_deceleration: f32,
+    rotary_allowance: f32,
+    tractive_effort: EffortCurve,
+    braking_effort: EffortCurve,
+    davis: Davis,
     pub position: f32,
     pub velocity: f32,
-    throttle: i16,
+    pub throttle: i16,
     acceleration: f32,
     pub target_velocity: f32,
     pub target_distance: f32,
     pub block_length: f32,
+    pub profile: Vec<Surface>, // of the current block
     max_throttle: i16,
     max_brake: i16,
     emergency_brake: i16,
-    emergency: bool,
+    pub emergency: bool,
 }
 
-impl <'a> Train <'a> {
-    pub fn new(name: &'a str, mass: f32, power: u32, engines: u32, width: f32, height: f32, acceleration: f32) -> Self {
+impl Train {
+    pub fn new(name: TrainId, rolling_stock: &RollingStock) -> Self {
         Train {
             name,
-            mass,
-            power: power * engines,
-            axle_resistance: 0.002 * mass * GRAVITY, // estimate of axle resistance (less than steel-steel)
-            rolling_resistance: 0.0015 * mass * GRAVITY, // steel-steel rolling resistance is 0.1-0.2%
-            air_resistance_coefficient: width * height, // requires (* v * v)
-            max_tractive_effort: (0.5 * mass * acceleration) as u32, // friction of steel-steel (0.15 for wet, 0.5 for dry)
+            length: rolling_stock.length,
+            max_speed: rolling_stock.max_speed,
+            mass: rolling_stock.mass,
+            power: rolling_stock.power,
+            max_tractive_effort: rolling_stock.max_tractive_effort,
+            service_deceleration: rolling_stock.service_brake,
+            emergency_deceleration: rolling_stock.emergency_brake,
+            rotary_allowance: rolling_stock.rotary_allowance,
+            tractive_effort: rolling_stock.tractive_effort.clone(),
+            braking_effort: rolling_stock.braking_effort.clone(),
+            davis: rolling_stock.davis,
             position: 0.0,
             velocity: 0.0,
             throttle: 0,
@@ -47,6 +123,7 @@ impl <'a> Train <'a> {
             target_velocity: 0.0,
             target_distance: 0.0,
             block_length: 0.0,
+            profile: Vec::new(),
             max_throttle: 100,
             max_brake: 150,
             emergency_brake: 200,
@@ -63,25 +140,50 @@ impl <'a> Train <'a> {
             self.throttle = 0;
         }
 
-        let mut resistive_force = 0.0;
-        
-        resistive_force += self.axle_resistance;
-        resistive_force += self.rolling_resistance;
-        resistive_force += self.air_resistance_coefficient * self.velocity.powi(2);
+        self.acceleration = self.force() / (self.mass * (1.0 + self.rotary_allowance));
+
+        self.velocity = (self.velocity + self.acceleration * delta_time).max(0.0); // brakes and resistance stop a train, they don't reverse it
+        self.position += self.velocity * delta_time;
+    }
 
-        let propulsion_force = (f32::from(self.throttle) / 100.0) * std::cmp::min(self.max_tractive_effort, (self.power as f32 / self.velocity.abs()) as u32) as f32;
+    // davis resistance a + bv + cv^2
+    fn resistance(&self) -> f32 {
+        self.davis.c.mul_add(self.velocity.powi(2), self.davis.b.mul_add(self.velocity, self.davis.a))
+    }
 
-        let force = self.velocity.signum().mul_add(-resistive_force, propulsion_force);
+    // positive throttle is a share of the tractive effort available at this speed, negative is a share of full service braking
+    fn force(&self) -> f32 {
+        let speed = self.velocity.abs();
+        let propulsion_force = if self.emergency {
+            -self.emergency_deceleration * self.mass
+        }
+        else if self.throttle >= 0 {
+            let tractive_effort = self.tractive_effort.at(speed).unwrap_or(self.max_tractive_effort.min(self.power / speed));
+            (f32::from(self.throttle) / f32::from(self.max_throttle)) * tractive_effort
+        }
+        else {
+            let braking_effort = self.braking_effort.at(speed).unwrap_or(self.service_deceleration * self.mass);
+            (f32::from(self.throttle) / f32::from(self.max_brake)).max(-1.0) * braking_effort
+        };
 
-        self.acceleration = force / self.mass;
+        let surface = surface_at(&self.profile, self.position);
+        let resistance = self.resistance() + surface.curve_force(self.mass);
+        self.velocity.signum().mul_add(-resistance, propulsion_force) - surface.gradient_force(self.mass)
+    }
 
-        self.velocity += self.acceleration * delta_time;
-        self.position += self.velocity * delta_time;
+    // how far short of a stop the brakes have to go on, at a share of full service braking to keep some in hand
+    // and with the last few metres that control creeps over
+    pub fn braking_distance(&self) -> f32 {
+        self.velocity.powi(2) / (2.0 * STOPPING_BRAKE * self.service_deceleration) + 10.0
     }
 
     fn control(&mut self) {
         debug!("control");
-        let target_acceleration = self.velocity.mul_add(-self.velocity, self.target_velocity.powi(2)) / (2.0 * (self.target_distance - 5.0));
+        if self.target_velocity == 0.0 && (self.target_distance < 5.0 || self.velocity == 0.0 && self.target_distance < 10.0) {
+            self.throttle = -self.max_brake; // stood at (or creeping the last few metres to) a stop or signal, hold the brakes rather than hunting around zero
+            return;
+        }
+        let target_acceleration = self.velocity.mul_add(-self.velocity, self.target_velocity.powi(2)) / (2.0 * (self.target_distance - 5.0).max(0.1)); // a train stood at a signal that clears can still pull away
         debug!("target acceleration {}", target_acceleration);
         debug!("throttle {}", self.throttle);
         // v^2 = u^2 + 2as
@@ -103,8 +205,60 @@ impl <'a> Train <'a> {
     }
 }
 
-impl <'a> std::fmt::Display for Train <'a> {
+impl std::fmt::Display for Train {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Vel: {:>6.2}m/s : {:>6.2}mph | Target {:>8.2}mph in {:>8.2}m | {} | ", self.velocity, convert_to_mph(self.velocity), convert_to_mph(self.target_velocity), self.target_distance, if self.emergency{"EMERGENCY"} else {"OK"})
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_effort_curve() {
+    let curve = EffortCurve(vec![(0.0, 100.0), (10.0, 100.0), (20.0, 50.0)]);
+    assert_eq!(curve.at(5.0), Some(100.0));
+    assert_eq!(curve.at(15.0), Some(75.0));
+    assert_eq!(curve.at(30.0), Some(50.0));
+    assert_eq!(EffortCurve::default().at(5.0), None);
+
+    assert!(EffortCurve::try_from(Vec::new()).is_err());
+    assert!(EffortCurve::try_from(vec![(0.0, 100.0), (20.0, 50.0), (10.0, 100.0)]).is_err());
+    assert!(EffortCurve::try_from(vec![(0.0, 100.0), (0.0, 50.0)]).is_err());
+}
+
+#[test]
+fn test_performance() {
+    let rolling_stock = crate::utils::io::parse_rolling_stock(crate::utils::io::ROLLING_STOCK).unwrap();
+    let mut train = Train::new("1A01".into(), crate::utils::io::find_class(&rolling_stock, "802").unwrap());
+    train.throttle = 100;
+
+    // full tractive effort less davis a, over the mass plus its rotary allowance
+    train.velocity = 0.1;
+    assert!((train.force() / (250000.0 * 1.08) - 0.54).abs() < 0.01);
+
+    // still accelerating at its top speed, but not far beyond it
+    train.velocity = 55.9;
+    assert!(train.force() > 0.0);
+    train.velocity = 65.0;
+    assert!(train.force() < 0.0);
+}
+
+#[test]
+fn test_gradient() {
+    let rolling_stock = crate::utils::io::parse_rolling_stock(crate::utils::io::ROLLING_STOCK).unwrap();
+    let mut class = crate::utils::io::find_class(&rolling_stock, "66").unwrap().clone();
+    class.mass += 2000000.0; // 2000t of wagons behind
+    let mut train = Train::new("6M01".into(), &class);
+    train.throttle = 100;
+    train.velocity = 0.1;
+
+    // gets away on the level, stalls at 1 in 40
+    assert!(train.force() > 0.0);
+    train.profile = vec![Surface { start: 0.0, gradient: 0.025, radius: None }];
+    assert!(train.force() < 0.0);
+
+    // full service braking does less going downhill
+    train.throttle = -train.max_brake;
+    train.velocity = 20.0;
+    let uphill = train.force();
+    train.profile = vec![Surface { start: 0.0, gradient: -0.025, radius: Some(500.0) }];
+    assert!(train.force() > uphill);
+}
@@ -3,36 +3,203 @@ mod utils {
     pub mod conversion;
     pub mod io;
     pub mod visualiser;
-    pub mod bihashmap;
+    pub mod pathfinding;
+    pub mod events;
+    pub mod train_graph;
+    pub mod id;
+    pub mod replay;
+    pub mod schematic;
+    pub mod dashboard;
 }
-#[macro_use] mod infrastructure {
+mod infrastructure {
     pub mod signal;
     pub mod block;
     pub mod platform;
-    #[macro_use] pub mod train;
+    pub mod points;
+    pub mod train;
 }
 mod control {
     pub mod signaller;
     pub mod driver;
     pub mod message;
+    pub mod occupancy;
+    pub mod console;
+    pub mod remote;
+    pub mod clock;
+    pub mod ars;
 }
 mod simulation;
 
-use crate::simulation::Simulation;
+use crate::{simulation::Simulation, control::ars::DEFAULT_LOOK_AHEAD, utils::{io::{load_scenario, build_network}, replay::replay}};
+use clap::Parser;
 
 const GRAVITY: f32 = 9.81;
 
+/// Run a train simulation scenario
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// scenario file describing the network, trains and simulation parameters
+    #[arg(default_value = "scenarios/default.yaml")]
+    scenario: String,
+
+    /// simulated seconds to run for
+    #[arg(long)]
+    duration: Option<f32>,
+
+    /// simulated seconds per tick
+    #[arg(long)]
+    delta_time: Option<f32>,
+
+    /// ticks between visualiser updates
+    #[arg(long)]
+    ticks_per_update: Option<u32>,
+
+    /// simulated time relative to real time
+    #[arg(long)]
+    speedup: Option<f32>,
+
+    /// write every simulation event to this file as JSON Lines
+    #[arg(long)]
+    events: Option<String>,
+
+    /// draw a time-distance diagram of the corridor to this SVG file
+    #[arg(long, requires = "corridor")]
+    train_graph: Option<String>,
+
+    /// blocks making up the corridor in order, comma separated
+    #[arg(long, value_delimiter = ',')]
+    corridor: Vec<String>,
+
+    /// save the state of the run to this file once it finishes
+    #[arg(long)]
+    snapshot: Option<String>,
+
+    /// carry on from a snapshot of a run of the same scenario, until the same --duration
+    #[arg(long)]
+    resume: Option<String>,
+
+    /// record every frame the visualiser would draw to this file, for --replay
+    #[arg(long)]
+    record: Option<String>,
+
+    /// play a recording back instead of running a scenario, at --speedup
+    #[arg(long, conflicts_with_all = ["record", "headless", "resume"])]
+    replay: Option<String>,
+
+    /// run as fast as possible without the visualiser, printing only the final state and timetable report
+    #[arg(long)]
+    headless: bool,
+
+    /// serve a page showing the run live on this address, such as 127.0.0.1:8080
+    #[arg(long, conflicts_with = "replay")]
+    dashboard: Option<String>,
+
+    /// signal by hand and pause, step or change the speed from the keyboard while the run is drawn
+    #[arg(long, conflicts_with_all = ["headless", "replay"])]
+    interactive: bool,
+
+    /// let drivers connect from outside on this endpoint, tcp:<host>:<port> or unix:<path> (see PROTOCOL.md)
+    #[arg(long, conflicts_with = "replay")]
+    listen: Option<String>,
+
+    /// wait for a signaller to connect on --listen and leave all the signalling to it
+    #[arg(long, requires = "listen", conflicts_with = "interactive")]
+    remote_signaller: bool,
+
+    /// set routes ahead of trains automatically from their timetables, by priority then lateness
+    #[arg(long, conflicts_with = "remote_signaller")]
+    ars: bool,
+
+    /// signals beyond the one each train is approaching that --ars sets routes for
+    #[arg(long, requires = "ars")]
+    look_ahead: Option<usize>,
+
+    /// refuse any movement that would leave trains waiting on each other for good
+    #[arg(long, conflicts_with = "remote_signaller")]
+    avoid_deadlocks: bool,
+}
+
 fn main() {
     if cfg!(feature = "logging") {
         env_logger::init();
     }
 
-    let duration = 40000.0;
-    let delta_time = 0.01;
-    let ticks_per_update = 5;
-    let speedup = 50.0;
+    let args = Args::parse();
+
+    if let Some(path) = &args.replay {
+        replay(path, args.speedup.unwrap_or(1.0)).unwrap_or_else(|e| exit_with(&format!("failed to replay: {e}")));
+        return;
+    }
+
+    let scenario = load_scenario(&args.scenario).unwrap_or_else(|e| exit_with(&format!("failed to load scenario: {e}")));
+    let parameters = &scenario.simulation;
+
+    let duration = args.duration.unwrap_or(parameters.duration);
+    let delta_time = args.delta_time.unwrap_or(parameters.delta_time);
+    let ticks_per_update = args.ticks_per_update.unwrap_or(parameters.ticks_per_update);
+    let speedup = args.speedup.unwrap_or(parameters.speedup);
+
+    let network = build_network(&scenario.network).unwrap_or_else(|e| exit_with(&format!("invalid network: {e}")));
     
-    let simulation = Simulation::new(duration, delta_time, ticks_per_update, speedup);
+    let mut simulation = Simulation::new(duration, delta_time, ticks_per_update, speedup, network, &scenario.trains, &scenario.rolling_stock).unwrap_or_else(|e| exit_with(&format!("invalid scenario: {e}")));
+
+    if let Some(path) = &args.resume {
+        simulation.resume(path).unwrap_or_else(|e| exit_with(&format!("failed to resume: {e}")));
+    }
+
+    if let Some(path) = &args.snapshot {
+        simulation.record_snapshot(path);
+    }
+
+    if let Some(path) = &args.record {
+        simulation.record_frames(path).unwrap_or_else(|e| exit_with(&format!("failed to open recording: {e}")));
+    }
+
+    if let Some(path) = &args.events {
+        simulation.record_events(path).unwrap_or_else(|e| exit_with(&format!("failed to open event log: {e}")));
+    }
+
+    if let Some(path) = &args.train_graph {
+        let corridor: Vec<&str> = args.corridor.iter().map(String::as_str).collect();
+        simulation.record_train_graph(path, &corridor).unwrap_or_else(|e| exit_with(&format!("invalid corridor: {e}")));
+    }
+
+    if let Some(address) = &args.dashboard {
+        let address = simulation.open_dashboard(address).unwrap_or_else(|e| exit_with(&format!("failed to start dashboard: {e}")));
+        eprintln!("dashboard at http://{address}");
+    }
+
+    if let Some(endpoint) = &args.listen {
+        let endpoint = simulation.listen(endpoint, args.remote_signaller).unwrap_or_else(|e| exit_with(&format!("failed to listen: {e}")));
+        eprintln!("listening on {endpoint}");
+        if args.remote_signaller {
+            eprintln!("waiting for a signaller on {endpoint}");
+            simulation.await_signaller();
+        }
+    }
+
+    if args.ars {
+        simulation.automate(args.look_ahead.unwrap_or(DEFAULT_LOOK_AHEAD), &scenario.trains);
+    }
+
+    if args.avoid_deadlocks {
+        simulation.avoid_deadlocks();
+    }
+
+    if args.interactive {
+        simulation.open_console();
+    }
+
+    if args.headless {
+        simulation.run_headless();
+    }
+    else {
+        simulation.run();
+    }
+}
 
-    simulation.run();
+fn exit_with(message: &str) -> ! {
+    eprintln!("error: {message}");
+    std::process::exit(1);
 }
\ No newline at end of file
@@ -1,123 +1,554 @@
 use crate::{
-    infrastructure::{
-        signal::Signal, block::Block, train::*
-    },
+    infrastructure::train::{Train, RollingStock},
     control::{
-        driver::Driver, signaller::Signaller, message::*,
-    }, utils::visualiser::Visualiser
+        driver::{Driver, DriverState, Stop}, signaller::{Signaller, SignallerState}, message::*, console::{Action, Console}, remote::{Addressed, Gateway, RemoteSignaller}, clock::{Clock, Phase}, ars::Ars,
+    }, utils::{visualiser::{Frame, Visualiser}, schematic::Schematic, replay::Recording, dashboard::Dashboard, id::{BlockId, TrainId}, io::{YamlTrain, DEAD_END, Network, find_class}, pathfinding::plan_route, events::EventLog, train_graph::TrainGraph}
 };
-use petgraph::prelude::DiGraphMap;
-use rayon::prelude::*;
-use std::{fmt::Debug, sync::{Arc, Mutex}, time::{Duration, self}};
+use serde::{Deserialize, Serialize};
+use std::{fs, net::SocketAddr, time::{Duration, Instant}};
 use std::thread;
-use std::sync::mpsc::{channel, sync_channel, Sender, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 
-use log::{info};
+use log::{error, info};
 
-const BUF_SIZE: usize = 10;
+const MAX_SPEEDUP: f32 = 1024.0;
 
-#[derive(Clone, Debug, PartialEq)]
-struct YamlTrack {
-    name: String,
-    length: f32,
-    limit: f32,
-    reverse: String,
-    next_tracks: Vec<String>
-}
-
-pub struct Simulation <'a> {
+pub struct Simulation {
+    time: f32,
+    tick: u32,
     duration: f32,
     delta_time: f32,
     ticks_per_update: u32,
     speedup: f32,
     visualiser: Visualiser,
-    signaller: Signaller <'a>,
-    drivers: Vec<Driver<'a>>
+    clock: Clock,
+    signaller: Arc<Mutex<Signaller>>, // shared with the agents' tasks, and only locked between their phases
+    drivers: Vec<Arc<Mutex<Driver>>>,
+    train_tx: UnboundedSender<TrainMessage>, // for trains joining from outside
+    events: Option<EventLog>,
+    recording: Option<Recording>,
+    train_graph: Option<(String, TrainGraph)>,
+    snapshot: Option<String>, // where to save the state of the run once it finishes
+    console: Option<Console>,
+    dashboard: Option<Dashboard>,
+    gateway: Option<Gateway>,
+    remote_signaller: Option<RemoteSignaller>, // doing all the signalling in place of `signaller`
+    ars: Option<Ars>,
+}
+
+// everything needed to carry on a run from where it was saved, given the same scenario
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    time: f32,
+    #[serde(default)]
+    tick: u32, // so frames go on being recorded and drawn on the same ticks as if the run hadn't stopped
+    signaller: SignallerState,
+    drivers: Vec<DriverState>,
 }
 
-impl <'a> Simulation <'a> {
-    pub fn new(duration: f32, delta_time: f32, ticks_per_update: u32, speedup: f32) -> Self {
-        let (train_tx, signaller_rx) = sync_channel::<TrainMessage>(BUF_SIZE);
+impl Simulation {
+    pub fn new(duration: f32, delta_time: f32, ticks_per_update: u32, speedup: f32, network: Network, trains: &[YamlTrain], rolling_stock: &[RollingStock]) -> Result<Self, String> {
+        let (train_tx, signaller_rx) = unbounded_channel::<TrainMessage>();
+
+        for train in trains {
+            if !network.contains_node(BlockId::new(&train.start)) || train.start == DEAD_END.as_str() {
+                return Err(format!("train {} starts on unknown block {}", train.id, train.start));
+            }
+        }
+
+        let drivers: Vec<_> = init_drivers(train_tx.clone(), delta_time, trains, rolling_stock, &network)?.into_iter().map(|driver| Arc::new(Mutex::new(driver))).collect();
+        let visualiser = Visualiser::new(Schematic::new(&network));
+        let signaller = Arc::new(Mutex::new(Signaller::new(signaller_rx, network)));
 
-        return Simulation {
+        let mut clock = Clock::new();
+        clock.spawn(Phase::Signalling, signaller.clone());
+        for driver in &drivers {
+            clock.spawn(Phase::Driving, driver.clone());
+        }
+
+        Ok(Simulation {
+            time: 0.0,
+            tick: 0,
             duration,
             delta_time,
-            ticks_per_update: ticks_per_update + 1,
+            ticks_per_update: ticks_per_update.max(1),
             speedup,
-            visualiser: Visualiser::new(),
-            signaller: Signaller::new(signaller_rx, init_network()),
-            drivers: init_drivers(train_tx, delta_time)
+            visualiser,
+            clock,
+            signaller,
+            drivers,
+            train_tx,
+            events: None,
+            recording: None,
+            train_graph: None,
+            snapshot: None,
+            console: None,
+            dashboard: None,
+            gateway: None,
+            remote_signaller: None,
+            ars: None,
+        })
+    }
+
+    pub fn record_events(&mut self, path: &str) -> Result<(), String> {
+        self.events = Some(EventLog::create(path)?);
+        Ok(())
+    }
+
+    pub fn record_frames(&mut self, path: &str) -> Result<(), String> {
+        self.recording = Some(Recording::create(path, self.visualiser.schematic())?);
+        Ok(())
+    }
+
+    pub fn record_train_graph(&mut self, path: &str, corridor: &[&str]) -> Result<(), String> {
+        self.train_graph = Some((path.to_string(), TrainGraph::new(corridor, &self.signaller.lock().unwrap().network)?));
+        Ok(())
+    }
+
+    pub fn record_snapshot(&mut self, path: &str) {
+        self.snapshot = Some(path.to_string());
+    }
+
+    // serve the run to a browser while it goes, on the address the server ended up with
+    pub fn open_dashboard(&mut self, address: &str) -> Result<SocketAddr, String> {
+        let dashboard = Dashboard::start(address, self.visualiser.schematic().clone(), &self.clock.handle())?;
+        let address = dashboard.address();
+        self.dashboard = Some(dashboard);
+        Ok(address)
+    }
+
+    // let drivers, and a signaller if asked for, connect from outside, on the endpoint the gateway ended up with
+    pub fn listen(&mut self, endpoint: &str, remote_signaller: bool) -> Result<String, String> {
+        let trains: Vec<TrainId> = lock(&self.drivers).iter().map(|driver| driver.train.name).collect();
+        let gateway = Gateway::open(endpoint, self.train_tx.clone(), &self.signaller.lock().unwrap().network, &trains, remote_signaller, &self.clock.handle())?;
+        let endpoint = gateway.endpoint().to_string();
+        self.gateway = Some(gateway);
+        Ok(endpoint)
+    }
+
+    pub fn await_signaller(&mut self) {
+        if let Some(gateway) = &mut self.gateway {
+            self.remote_signaller = Some(gateway.await_signaller());
         }
     }
 
-    pub fn run(mut self) {
-        let mut time_elapsed = 0.0;
+    // set routes ahead of trains from their timetables, choosing who goes first by priority and lateness
+    pub fn automate(&mut self, look_ahead: usize, trains: &[YamlTrain]) {
+        self.ars = Some(Ars::new(look_ahead, trains));
+    }
+
+    // hold trains back rather than let them into blocks where they would be left waiting on each other for good
+    pub fn avoid_deadlocks(&mut self) {
+        self.signaller.lock().unwrap().avoid_deadlocks();
+    }
 
-        let mut ticks = 0;
+    // take keyboard input while the run is drawn, to signal by hand and work the clock
+    pub fn open_console(&mut self) {
+        self.console = Some(Console::open());
+    }
+
+    // carry on from a saved run, which must have been of the same scenario
+    pub fn resume(&mut self, path: &str) -> Result<(), String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        let snapshot = serde_json::from_str(&contents).map_err(|e| format!("could not parse {path}: {e}"))?;
+        self.restore(snapshot)
+    }
 
+    fn save(&mut self, path: &str) -> Result<(), String> {
+        let contents = serde_json::to_string(&self.take_snapshot()).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("could not write {path}: {e}"))
+    }
+
+    fn take_snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            time: self.time,
+            tick: self.tick,
+            signaller: self.signaller.lock().unwrap().snapshot(),
+            drivers: lock(&self.drivers).iter().map(|driver| driver.snapshot()).collect(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        if snapshot.drivers.len() != self.drivers.len() {
+            return Err(format!("snapshot has {} trains but the scenario has {}", snapshot.drivers.len(), self.drivers.len()));
+        }
+        for (driver, state) in self.drivers.iter().zip(snapshot.drivers) {
+            driver.lock().unwrap().restore(state)?;
+        }
+        self.signaller.lock().unwrap().restore(snapshot.signaller)?;
+        self.time = snapshot.time;
+        self.tick = snapshot.tick;
+        Ok(())
+    }
+
+    pub fn run(mut self) {
         let sleeper = spin_sleep::SpinSleeper::default();
+        info!("running with interval {}", self.delta_time / self.speedup);
+
+        let mut paused = false;
+        let mut next_tick = Instant::now();
 
-        let (tx, rx) = channel();
+        while self.time < self.duration {
+            let mut step = !paused;
+            let mut redraw = false;
+
+            if let Some(console) = &mut self.console {
+                for action in console.poll() {
+                    match action {
+                        Action::Pause => paused = !paused,
+                        Action::Step => step = true,
+                        Action::Faster => self.speedup = (self.speedup * 2.0).min(MAX_SPEEDUP),
+                        Action::Slower => self.speedup = (self.speedup / 2.0).max(1.0 / MAX_SPEEDUP),
+                        Action::Edit => (),
+                        Action::Command(command) => console.report(self.signaller.lock().unwrap().command(command)),
+                    }
+                    redraw = true;
+                }
+            }
 
-        let timer = std::thread::spawn(move || {
-            loop {
-                sleeper.sleep_s((self.delta_time / self.speedup) as f64);
-                tx.send(()).expect("Timer Error");
+            if step {
+                self.time_step(self.time);
+                redraw |= self.tick.is_multiple_of(self.ticks_per_update);
             }
-        });
 
-        info!("started timer with interval {}", self.delta_time / self.speedup);
-        
-        while time_elapsed < self.duration {
-            ticks += 1;
+            if redraw && !cfg!(feature = "logging") {
+                let frame = Frame::capture(self.time, &lock(&self.drivers), &self.signaller.lock().unwrap().network);
+                self.visualiser.update(&frame);
+                if let Some(console) = &self.console {
+                    self.visualiser.console(&console.line, &console.message, self.speedup, paused);
+                }
+            }
 
-            self.time_step();
+            if step {
+                self.time += &self.delta_time;
+            }
 
-            if ticks == self.ticks_per_update {
-                if !cfg!(feature = "logging") {
-                    self.visualiser.update(time_elapsed, &self.drivers, &self.signaller.network);
+            // ticks are kept to the clock from when the run was last resumed, so slow frames are caught up on
+            if paused {
+                thread::sleep(Duration::from_millis(20));
+                next_tick = Instant::now();
+            }
+            else {
+                next_tick += Duration::from_secs_f32(self.delta_time / self.speedup);
+                sleeper.sleep(next_tick.saturating_duration_since(Instant::now()));
+            }
+        }
+
+        self.visualiser.timetable(&lock(&self.drivers));
+        self.finish();
+    }
+
+    // steps as fast as possible without drawing, so the only output is where each train ended up and the timetable report
+    pub fn run_headless(mut self) {
+        while self.time < self.duration {
+            self.time_step(self.time);
+            self.time += &self.delta_time;
+        }
+
+        self.visualiser.status(&Frame::capture(self.time, &lock(&self.drivers), &self.signaller.lock().unwrap().network));
+        self.visualiser.timetable(&lock(&self.drivers));
+        self.finish();
+    }
+
+    fn time_step(&mut self, time_elapsed: f32) {
+        match &mut self.remote_signaller {
+            Some(remote) => {
+                let mut signaller = self.signaller.lock().unwrap();
+                remote.send(signaller.relay());
+                for Addressed { train_id, message } in remote.replies() {
+                    if let Err(e) = signaller.deliver(train_id, message) {
+                        error!("remote signaller: {e}");
+                    }
                 }
-                ticks = 1;
+            },
+            None => {
+                if let Some(ars) = &self.ars {
+                    let regulation = ars.regulate(time_elapsed, &lock(&self.drivers));
+                    self.signaller.lock().unwrap().regulate(regulation);
+                }
+                self.clock.run(Phase::Signalling);
+            },
+        }
+        self.clock.run(Phase::Driving);
+
+        let signaller = self.signaller.lock().unwrap();
+        let mut drivers = lock(&self.drivers);
+
+        // signaller first, then drivers in scenario order, so the log is the same every run
+        let mut events = signaller.take_events();
+        for driver in &mut drivers {
+            events.extend(driver.take_events());
+        }
+        if let Some(log) = &mut self.events {
+            for event in &events {
+                log.write(time_elapsed, event);
             }
-            
-            time_elapsed += &self.delta_time;
+        }
+        if let Some((_, train_graph)) = &mut self.train_graph {
+            train_graph.record(time_elapsed, &events, &drivers);
+        }
+
+        self.tick += 1;
+        if self.tick.is_multiple_of(self.ticks_per_update) && (self.recording.is_some() || self.dashboard.is_some()) {
+            let frame = Frame::capture(time_elapsed, &drivers, &signaller.network);
+            if let Some(recording) = &mut self.recording {
+                recording.write(&frame);
+            }
+            if let Some(dashboard) = &self.dashboard {
+                dashboard.publish(&frame, &drivers, &signaller.occupancy);
+            }
+        }
+    }
 
-            rx.recv().unwrap();
+    // write out anything recorded over the run
+    fn finish(&mut self) {
+        if let Some(events) = &mut self.events {
+            events.flush();
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.flush();
         }
+        if let Some((path, train_graph)) = &self.train_graph {
+            if let Err(e) = train_graph.write_svg(path) {
+                error!("{e}");
+            }
+        }
+        if let Some(path) = self.snapshot.clone() {
+            if let Err(e) = self.save(&path) {
+                error!("{e}");
+            }
+        }
+    }
+}
+
+// every driver at once, to read between ticks
+fn lock(drivers: &[Arc<Mutex<Driver>>]) -> Vec<MutexGuard<'_, Driver>> {
+    drivers.iter().map(|driver| driver.lock().unwrap()).collect()
+}
+
+fn init_drivers(tx: UnboundedSender<TrainMessage>, delta_time: f32, trains: &[YamlTrain], rolling_stock: &[RollingStock], network: &Network) -> Result<Vec<Driver>, String> {
+    let mut drivers = Vec::new();
+
+    for train in trains {
+        let id = TrainId::new(&train.id);
+        let start = BlockId::new(&train.start);
+        let timetable: Vec<Stop> = train.timetable.iter().map(|stop| Stop::new(BlockId::new(&stop.location), stop.platform, stop.arrival, stop.time, stop.dwell)).collect();
+        if train.timetable.iter().any(|stop| stop.platform == 0) {
+            return Err(format!("train {id} calls at platform 0, platforms are numbered from 1"));
+        }
+
+        // untimetabled trains just follow the points
+        let route = if timetable.is_empty() {
+            Vec::new()
+        }
+        else {
+            let locations: Vec<BlockId> = timetable.iter().map(|stop| stop.location).collect();
+            plan_route(network, start, &locations, train.routing).map_err(|e| format!("train {id}: {e}"))?
+        };
+
+        let class = find_class(rolling_stock, &train.class).ok_or(format!("train {id} has unknown class {}", train.class))?;
+
+        drivers.push(Driver::new(tx.clone(), Train::new(id, class), start, delta_time, timetable, route));
+        info!("added train to network: {}", id);
+    }
+
+    Ok(drivers)
+}
+
+#[test]
+fn test_deterministic() {
+    let run = || {
+        let (scenario, network) = crate::utils::io::passing_loop();
+        let mut simulation = Simulation::new(1500.0, 0.1, 5, 1.0, network, &scenario.trains, &scenario.rolling_stock).unwrap();
+        for tick in 0..15000 {
+            simulation.time_step(tick as f32 * 0.1);
+        }
+        let ends = lock(&simulation.drivers).iter().map(|driver| (driver.dst, driver.train.position, driver.timetable.clone())).collect::<Vec<_>>();
+        ends
+    };
+
+    let first = run();
+    assert!(first.iter().all(|(_, _, timetable)| timetable[0].departed.is_some()));
+    assert_eq!(first, run());
+}
 
-        drop(timer);
+#[test]
+fn test_resume() {
+    let simulation = || {
+        let (scenario, network) = crate::utils::io::passing_loop();
+        Simulation::new(1500.0, 0.1, 3, 1.0, network, &scenario.trains, &scenario.rolling_stock).unwrap()
+    };
+    let states = |simulation: &Simulation| lock(&simulation.drivers).iter().map(|driver| driver.snapshot()).map(|state| serde_json::to_string(&state).unwrap()).collect::<Vec<_>>();
+
+    let recording = |name: &str| std::env::temp_dir().join(format!("project_t_{}_{name}.json", std::process::id())).to_string_lossy().into_owned();
+    let (whole_path, second_path) = (recording("whole"), recording("resumed"));
+
+    let mut whole = simulation();
+    whole.record_frames(&whole_path).unwrap();
+    for tick in 0..15000 {
+        whole.time_step(tick as f32 * 0.1);
     }
+    whole.finish();
 
-    fn time_step(&mut self) {
-        self.signaller.update();
-        
-        self.drivers.par_iter_mut().for_each(|driver| {
-            driver.time_step();
-        });
+    // saved between frames as 1A01 runs into the loop with its request for the block still waiting, then carried on
+    // in a fresh simulation
+    let mut first = simulation();
+    for tick in 0..3440 {
+        first.time_step(tick as f32 * 0.1);
     }
+    let saved = serde_json::to_string(&first.take_snapshot()).unwrap();
+    assert!(saved.contains("ReserveNextBlock"));
+    let mut second = simulation();
+    second.restore(serde_json::from_str(&saved).unwrap()).unwrap();
+    second.record_frames(&second_path).unwrap();
+    for tick in 3440..15000 {
+        second.time_step(tick as f32 * 0.1);
+    }
+    second.finish();
+
+    assert_eq!(states(&whole), states(&second));
+
+    // and records the frames the uninterrupted run did from then on
+    let (_, whole_frames) = crate::utils::replay::load_recording(&whole_path).unwrap();
+    let (_, second_frames) = crate::utils::replay::load_recording(&second_path).unwrap();
+    let _ = (fs::remove_file(&whole_path), fs::remove_file(&second_path));
+    assert!(!second_frames.is_empty());
+    assert_eq!(whole_frames[whole_frames.len() - second_frames.len()..], second_frames[..]);
 }
 
-fn init_network<'a>() -> DiGraphMap::<&'a str, Arc<Mutex<Block<'a>>>> {
-    let mut network = DiGraphMap::<&str, Arc<Mutex<Block>>>::new();
+#[test]
+fn test_dashboard() {
+    use std::io::{Read, Write};
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let (scenario, network) = crate::utils::io::passing_loop();
+    let mut simulation = Simulation::new(100.0, 0.1, 5, 1.0, network, &scenario.trains, &scenario.rolling_stock).unwrap();
+    let address = simulation.open_dashboard("127.0.0.1:0").unwrap();
 
-    network.add_edge("F", "A", Arc::new(Mutex::new(Block::new_track(4000, 125.0, Signal::new()))));
-    network.add_edge("A", "B", Arc::new(Mutex::new(Block::new_track(4000, 125.0, Signal::new()))));
-    network.add_edge("B", "C", Arc::new(Mutex::new(Block::new_track(4000, 60.0, Signal::new()))));
-    network.add_edge("C", "D", Arc::new(Mutex::new(Block::new_track(4000, 60.0, Signal::new()))));
-    //network.add_edge("C", "D", Arc::new(Mutex::new(Block::new_station(4000, 30, vec![Platform::new(Signal::new(), 1000)]))));
-    network.add_edge("D", "E", Arc::new(Mutex::new(Block::new_track(4000, 125.0, Signal::new()))));
-    network.add_edge("E", "F", Arc::new(Mutex::new(Block::new_track(4000, 125.0, Signal::new()))));
+    let mut page = String::new();
+    let mut client = std::net::TcpStream::connect(address).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    client.read_to_string(&mut page).unwrap();
+    assert!(page.starts_with("HTTP/1.1 200 OK") && page.contains("<html"));
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let (mut socket, _) = runtime.block_on(tokio_tungstenite::connect_async(format!("ws://{address}/"))).unwrap();
+    for tick in 0..10 {
+        simulation.time_step(tick as f32 * 0.1);
+    }
 
-    network
+    let update: serde_json::Value = match runtime.block_on(socket.next()) {
+        Some(Ok(Message::Text(text))) => serde_json::from_str(&text).unwrap(),
+        other => panic!("expected the state, got {other:?}"),
+    };
+    assert_eq!(update["trains"][0]["train_id"], "1A01");
+    assert_eq!(update["occupancy"]["2B02"][0], "Er");
+    assert!(update["diagram"].as_array().is_some_and(|lines| !lines.is_empty()));
 }
 
-fn init_drivers<'a>(tx: SyncSender<TrainMessage<'a>>, delta_time: f32) -> Vec<Driver<'a>> {
-    let mut drivers = Vec::new();
-    
-    drivers.push(Driver::new(tx.clone(), class802!("802208"), "A", delta_time, vec![("D", 1, 2000)]));
-    info!("added train to network: {}", "802208");
-    drivers.push(Driver::new(tx.clone(), class802!("802212"), "C", delta_time, vec![("E", 1, 2000)]));
-    info!("added train to network: {}", "802212");
-
-    return drivers;
-}
\ No newline at end of file
+#[test]
+fn test_remote_driver() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let (scenario, network) = crate::utils::io::passing_loop();
+    let mut simulation = Simulation::new(100.0, 0.1, 5, 1.0, network, &scenario.trains, &scenario.rolling_stock).unwrap();
+    let endpoint = simulation.listen("tcp:127.0.0.1:0", false).unwrap();
+    let address = endpoint.strip_prefix("tcp:").unwrap();
+
+    let connect = |hello: &str| {
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        writeln!(stream, "{hello}").unwrap();
+        BufReader::new(stream)
+    };
+    let mut taken = connect(r#"{"HelloWorld":{"train_id":"1A01","block_id":"E","length":100.0}}"#);
+    let mut driver = connect(r#"{"HelloWorld":{"train_id":"9Z99","block_id":"E","length":100.0}}"#);
+
+    let mut line = String::new();
+    taken.read_line(&mut line).unwrap();
+    assert!(line.contains("already running"));
+
+    // nothing from a refused greeting is kept
+    let mut stranger = connect(r#"{"HelloWorld":{"train_id":"9Z97","block_id":"NOWHERE","length":100.0}}"#);
+    line.clear();
+    stranger.read_line(&mut line).unwrap();
+    assert!(line.contains("unknown block NOWHERE"));
+    assert_eq!((TrainId::lookup("9Z97"), BlockId::lookup("NOWHERE")), (None, None));
+
+    // the greeting is only handed over once the connection has been read
+    while !simulation.signaller.lock().unwrap().occupancy.positions().contains_key(&TrainId::from("9Z99")) {
+        thread::sleep(Duration::from_millis(10));
+        simulation.time_step(0.0);
+    }
+    line.clear();
+    driver.read_line(&mut line).unwrap();
+    let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert!(message["NewBlock"]["new_block_id"].is_string());
+}
+
+#[test]
+fn test_remote_refused() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let (scenario, network) = crate::utils::io::passing_loop();
+    let mut simulation = Simulation::new(100.0, 0.1, 5, 1.0, network, &scenario.trains, &scenario.rolling_stock).unwrap();
+    let endpoint = simulation.listen("tcp:127.0.0.1:0", false).unwrap();
+
+    let mut stream = std::net::TcpStream::connect(endpoint.strip_prefix("tcp:").unwrap()).unwrap();
+    writeln!(stream, r#"{{"HelloWorld":{{"train_id":"9Z98","block_id":"E","length":100.0}}}}"#).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    while !simulation.signaller.lock().unwrap().occupancy.positions().contains_key(&TrainId::from("9Z98")) {
+        thread::sleep(Duration::from_millis(10));
+        simulation.time_step(0.0);
+    }
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.contains("NewBlock"));
+
+    // a block that doesn't exist is refused and the connection closed, and the run carries on without it
+    writeln!(stream, r#"{{"TailCleared":{{"train_id":"9Z98","block_id":"NOPE"}}}}"#).unwrap();
+    let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
+    assert!(lines.last().is_some_and(|line| line.contains(r#""error":"unknown block NOPE""#)));
+    for tick in 0..10 {
+        simulation.time_step(tick as f32 * 0.1);
+    }
+    assert_eq!(simulation.signaller.lock().unwrap().occupancy.front("9Z98".into()), Some("E".into()));
+}
+
+#[test]
+fn test_remote_signaller_refused() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let (scenario, network) = crate::utils::io::passing_loop();
+    let mut simulation = Simulation::new(100.0, 0.1, 5, 1.0, network, &scenario.trains, &scenario.rolling_stock).unwrap();
+    let endpoint = simulation.listen("tcp:127.0.0.1:0", true).unwrap();
+    let mut stream = std::net::TcpStream::connect(endpoint.strip_prefix("tcp:").unwrap()).unwrap();
+    writeln!(stream, r#""HelloSignaller""#).unwrap();
+    simulation.await_signaller();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    // replies naming a block or a train the simulation doesn't have are answered and dropped, and nothing of them kept
+    writeln!(stream, r#"{{"train_id":"1A01","message":{{"NewBlock":{{"new_block_id":"NOWHERE2","colour":"green","limit":55.0,"length":2000,"profile":[],"reversed":false,"reversible":false}}}}}}"#).unwrap();
+    writeln!(stream, r#"{{"train_id":"9Z96","message":{{"UpdateBlock":{{"colour":"green","limit":55.0}}}}}}"#).unwrap();
+    let mut errors = Vec::new();
+    let mut line = String::new();
+    while errors.len() < 2 {
+        simulation.time_step(0.0);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        if line.contains("error") {
+            errors.push(line.clone());
+        }
+    }
+    assert!(errors[0].contains("unknown block NOWHERE2") && errors[1].contains("unknown train 9Z96"));
+    assert_eq!((BlockId::lookup("NOWHERE2"), TrainId::lookup("9Z96")), (None, None));
+
+    // a line that isn't a reply at all, here a colour as the enum is named rather than as it is sent, closes the connection
+    writeln!(stream, r#"{{"train_id":"1A01","message":{{"UpdateBlock":{{"colour":"Green","limit":55.0}}}}}}"#).unwrap();
+    let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
+    assert!(lines.last().is_some_and(|line| line.contains(r#""error":"bad message"#)));
+    for tick in 0..10 {
+        simulation.time_step(tick as f32 * 0.1);
+    }
+}
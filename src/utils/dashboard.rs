@@ -0,0 +1,148 @@
+use crate::{
+    infrastructure::signal::SignalColour,
+    control::{driver::Driver, occupancy::Occupancy},
+    utils::{id::{BlockId, SignalId, TrainId}, schematic::{Ink, Schematic, runs}, visualiser::Frame}
+};
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::{collections::BTreeMap, net::SocketAddr, ops::Deref};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, runtime::Handle, sync::watch};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+use log::{debug, info};
+
+const PAGE: &str = include_str!("dashboard.html");
+
+// a page served locally that anyone who can reach the address can watch the run on, sent the latest state
+// over a websocket every time the visualiser would draw, skipping any a slow browser misses
+pub struct Dashboard {
+    schematic: Schematic,
+    state: watch::Sender<String>,
+    address: SocketAddr,
+}
+
+#[derive(Serialize)]
+struct Update<'a> {
+    time: f32,
+    trains: Vec<TrainUpdate>,
+    occupancy: BTreeMap<TrainId, Vec<BlockId>>, // blocks each train is in, rearmost first
+    signals: &'a [(SignalId, SignalColour)],
+    diagram: Vec<Vec<(String, Ink)>>, // the schematic as drawn in the terminal, in runs of the same ink
+}
+
+#[derive(Serialize)]
+struct TrainUpdate {
+    train_id: TrainId,
+    block_id: BlockId,
+    platform: Option<usize>,
+    position: f32, // metres into the block
+    speed: f32, // metres per second
+    target_speed: f32,
+    emergency: bool,
+}
+
+impl Dashboard {
+    pub fn start(address: &str, schematic: Schematic, runtime: &Handle) -> Result<Self, String> {
+        let listener = std::net::TcpListener::bind(address).map_err(|e| format!("could not listen on {address}: {e}"))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        let address = listener.local_addr().map_err(|e| e.to_string())?;
+        let (state, rx) = watch::channel(String::new());
+
+        // served alongside the agents, which never wait on anyone watching
+        runtime.spawn(serve(listener, rx));
+        info!("dashboard at http://{address}");
+
+        Ok(Dashboard { schematic, state, address })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn publish(&self, frame: &Frame, drivers: &[impl Deref<Target = Driver>], occupancy: &Occupancy) {
+        let trains = drivers.iter().zip(&frame.positions).map(|(driver, (train_id, signal_id, _))| TrainUpdate {
+            train_id: *train_id,
+            block_id: signal_id.block_id,
+            platform: signal_id.platform,
+            position: driver.train.position,
+            speed: driver.train.velocity,
+            target_speed: driver.train.target_velocity,
+            emergency: driver.train.emergency,
+        }).collect();
+
+        let update = Update {
+            time: frame.time,
+            trains,
+            occupancy: occupancy.positions(),
+            signals: &frame.signals,
+            diagram: self.schematic.draw(frame).iter().map(|line| runs(line)).collect(),
+        };
+        self.state.send_replace(serde_json::to_string(&update).unwrap());
+    }
+}
+
+async fn serve(listener: std::net::TcpListener, state: watch::Receiver<String>) {
+    let listener = TcpListener::from_std(listener).unwrap();
+    while let Ok((stream, peer)) = listener.accept().await {
+        debug!("dashboard connection from {peer}");
+        tokio::spawn(connect(stream, state.clone()));
+    }
+}
+
+// the page for a plain request, the state for a websocket upgrade to the same address
+async fn connect(stream: TcpStream, state: watch::Receiver<String>) {
+    let mut request = [0; 2048];
+    let Ok(read) = stream.peek(&mut request).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&request[..read]).to_ascii_lowercase();
+
+    if request.contains("upgrade: websocket") {
+        stream_state(stream, state).await;
+    }
+    else {
+        let found = request.starts_with("get / ") || request.starts_with("get /index.html ");
+        let _ = send_page(stream, found).await;
+    }
+}
+
+async fn send_page(mut stream: TcpStream, found: bool) -> std::io::Result<()> {
+    let mut request = [0; 2048];
+    let _ = stream.read(&mut request).await?;
+    let (status, body) = if found { ("200 OK", PAGE) } else { ("404 Not Found", "not found") };
+    let content_type = if found { "text/html; charset=utf-8" } else { "text/plain" };
+    let header = format!("HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn stream_state(stream: TcpStream, mut state: watch::Receiver<String>) {
+    let Ok(mut socket) = accept_async(stream).await else {
+        return;
+    };
+
+    // whatever there is so far, then every change until the run is over or the page is closed
+    let latest = state.borrow_and_update().clone();
+    if !latest.is_empty() && socket.send(Message::Text(latest)).await.is_err() {
+        return;
+    }
+    loop {
+        tokio::select! {
+            changed = state.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let latest = state.borrow_and_update().clone();
+                if socket.send(Message::Text(latest)).await.is_err() {
+                    return;
+                }
+            },
+            incoming = socket.next() => {
+                if !matches!(incoming, Some(Ok(_))) {
+                    return;
+                }
+            },
+        }
+    }
+    let _ = socket.close(None).await;
+}
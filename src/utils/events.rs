@@ -0,0 +1,95 @@
+use crate::{infrastructure::signal::{Owner, SignalColour}, utils::id::{BlockId, SignalId, TrainId}};
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io::{BufWriter, Write}};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    NextBlock,
+    ReverseBlock,
+    Route,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleState {
+    Power,
+    Brake,
+    Emergency,
+}
+
+impl ThrottleState {
+    // drivers hunt either side of an idle throttle to hold a speed, so idling keeps whatever came before
+    pub fn of(throttle: i16, emergency: bool, previous: ThrottleState) -> Self {
+        match throttle {
+            _ if emergency => ThrottleState::Emergency,
+            1.. => ThrottleState::Power,
+            0 if previous != ThrottleState::Emergency => previous,
+            _ => ThrottleState::Brake,
+        }
+    }
+}
+
+// everything worth knowing about a run, platforms are numbered from 1 as in the timetable
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    BlockEntered { train_id: TrainId, block_id: BlockId, platform: Option<usize> },
+    BlockCleared { train_id: TrainId, block_id: BlockId }, // the rear of the train has left the block
+    SignalChanged {
+        #[serde(flatten)]
+        signal_id: SignalId,
+        colour: SignalColour,
+        owner: &'static str,
+    },
+    Requested { train_id: TrainId, block_id: BlockId, request: Request },
+    RouteSet { train_id: TrainId, block_id: BlockId, next_block_id: BlockId },
+    RouteCancelled { train_id: TrainId, block_id: BlockId, next_block_id: BlockId },
+    Deadlock { train_ids: Vec<TrainId>, block_ids: Vec<BlockId> }, // trains held at blocks, each waiting on another for good
+    ThrottleChanged { train_id: TrainId, state: ThrottleState, throttle: i16 },
+    EmergencyBrake { train_id: TrainId, block_id: BlockId, velocity: f32 },
+    Arrived { train_id: TrainId, location: BlockId, platform: usize, booked: Option<u32> },
+    Departed { train_id: TrainId, location: BlockId, platform: usize, booked: u32 },
+}
+
+pub fn owner_name(owner: Owner) -> &'static str {
+    match owner {
+        Owner::Signaller => "signaller",
+        Owner::Train { id } => id.as_str(),
+    }
+}
+
+#[derive(Serialize)]
+struct Record <'e> {
+    time: f32,
+    #[serde(flatten)]
+    event: &'e Event,
+}
+
+// one JSON object per line, each stamped with the simulation time it happened at
+pub struct EventLog {
+    writer: BufWriter<File>,
+}
+
+impl EventLog {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("could not create {path}: {e}"))?;
+        Ok(EventLog { writer: BufWriter::new(file) })
+    }
+
+    pub fn write(&mut self, time: f32, event: &Event) {
+        serde_json::to_writer(&mut self.writer, &Record { time, event }).unwrap();
+        self.writer.write_all(b"\n").unwrap();
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().unwrap();
+    }
+}
+
+#[test]
+fn test_record() {
+    let event = Event::SignalChanged { signal_id: SignalId::new("3".into(), None), colour: SignalColour::DoubleYellow, owner: owner_name(Owner::Train { id: "1A01".into() }) };
+    let line = serde_json::to_string(&Record { time: 12.5, event: &event }).unwrap();
+    assert_eq!(line, r#"{"time":12.5,"event":"signal_changed","block_id":"3","platform":null,"colour":"double_yellow","owner":"1A01"}"#);
+}
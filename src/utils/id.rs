@@ -0,0 +1,129 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashSet, fmt, sync::{Mutex, OnceLock}};
+
+static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+// each distinct name is kept once for the rest of the run, so ids made at runtime
+// from files, messages or a console are as cheap to copy and compare as literals
+fn intern(name: &str) -> &'static str {
+    let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+    match names.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.to_owned().into_boxed_str());
+            names.insert(interned);
+            interned
+        },
+    }
+}
+
+// a name already interned, without keeping one that isn't, for names from outside that may never be let in
+fn interned(name: &str) -> Option<&'static str> {
+    NAMES.get_or_init(Default::default).lock().unwrap().get(name).copied()
+}
+
+macro_rules! interned_id {
+    ($id:ident) => {
+        // compared and ordered by name, so sorting by id repeats exactly between runs
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $id(&'static str);
+
+        impl $id {
+            pub fn new(name: &str) -> Self {
+                $id(intern(name))
+            }
+
+            pub fn lookup(name: &str) -> Option<Self> {
+                interned(name).map($id)
+            }
+
+            pub fn as_str(&self) -> &'static str {
+                self.0
+            }
+        }
+
+        impl From<&str> for $id {
+            fn from(name: &str) -> Self {
+                $id::new(name)
+            }
+        }
+
+        impl PartialEq<&str> for $id {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl fmt::Display for $id {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.pad(self.0)
+            }
+        }
+
+        impl fmt::Debug for $id {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:?}", self.0)
+            }
+        }
+
+        impl Serialize for $id {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $id {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok($id::new(&String::deserialize(deserializer)?))
+            }
+        }
+    };
+}
+
+interned_id!(TrainId);
+interned_id!(BlockId);
+
+impl BlockId {
+    // for constants such as the boundary node, equal to the same name interned at runtime
+    pub const fn from_static(name: &'static str) -> Self {
+        BlockId(name)
+    }
+}
+
+// signals aren't named in the track files, each one ends a block or one of a station's platforms
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SignalId {
+    pub block_id: BlockId,
+    pub platform: Option<usize>, // numbered from 1 as in the timetable
+}
+
+impl SignalId {
+    pub fn new(block_id: BlockId, platform: Option<usize>) -> Self {
+        SignalId { block_id, platform }
+    }
+}
+
+impl fmt::Display for SignalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.platform {
+            Some(platform) => write!(f, "{}/{platform}", self.block_id),
+            None => write!(f, "{}", self.block_id),
+        }
+    }
+}
+
+#[test]
+fn test_intern() {
+    let name = String::from("1A01");
+    let train_id = TrainId::new(&name);
+    drop(name);
+
+    assert_eq!(train_id, "1A01");
+    assert!(std::ptr::eq(train_id.as_str(), TrainId::new("1A01").as_str()));
+    assert_eq!(BlockId::new("-1"), crate::utils::io::DEAD_END);
+    assert!(BlockId::new("A") < BlockId::new("B"));
+    assert_eq!(TrainId::lookup("1A01"), Some(train_id));
+    assert_eq!(TrainId::lookup("never made"), None);
+    assert_eq!(BlockId::lookup("never made"), None);
+    assert_eq!(SignalId::new(BlockId::new("S"), Some(2)).to_string(), "S/2");
+}
@@ -0,0 +1,315 @@
+use crate::{
+    infrastructure::{
+        signal::Signal, block::Block, platform::Platform, points::Points, train::RollingStock
+    },
+    utils::{id::BlockId, pathfinding::RouteMetric, surface::{Surface, reverse_profile}}
+};
+use petgraph::graphmap::DiGraphMap;
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path, sync::{Arc, Mutex}};
+
+/// sentinel used in network files for "no track" (dead end in next_tracks, no pairing in reverse)
+/// also used as the boundary node that entry blocks hang off in the network graph
+pub const DEAD_END: BlockId = BlockId::from_static("-1");
+
+/// blocks by id, every edge into a block carrying it so junctions see the same signal
+pub type Network = DiGraphMap<BlockId, Arc<Mutex<Block>>>;
+
+/// classes every scenario can use without listing them itself
+pub const ROLLING_STOCK: &str = include_str!("../../rolling_stock.yaml");
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct YamlTrack {
+    pub name: String,
+    pub length: f32,
+    pub limit: f32,
+    pub reverse: String,
+    pub next_tracks: Vec<String>,
+    #[serde(default)]
+    pub platforms: Vec<f32>, // lengths of each platform, a track with any is a station
+    #[serde(default)]
+    pub profile: Vec<Surface>, // level and straight if empty, or the reverse track's profile the other way round if that has one
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct YamlParameters {
+    pub duration: f32,
+    pub delta_time: f32,
+    pub ticks_per_update: u32,
+    pub speedup: f32,
+}
+
+impl Default for YamlParameters {
+    fn default() -> Self {
+        YamlParameters {
+            duration: 40000.0,
+            delta_time: 0.01,
+            ticks_per_update: 5,
+            speedup: 50.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct YamlStop {
+    pub location: String,
+    pub platform: usize,
+    pub time: u32, // booked departure in seconds from the start, or arrival at the last stop
+    #[serde(default)]
+    pub arrival: Option<u32>,
+    #[serde(default)]
+    pub dwell: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct YamlTrain {
+    pub id: String,
+    pub class: String,
+    pub start: String,
+    #[serde(default)]
+    pub routing: RouteMetric,
+    #[serde(default)]
+    pub priority: u32, // higher goes first where automatic route setting has to choose between trains
+    #[serde(default)]
+    pub timetable: Vec<YamlStop>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct YamlScenario {
+    #[serde(default)]
+    pub simulation: YamlParameters,
+    #[serde(default)]
+    pub network_file: Option<String>, // relative to the scenario file, appended to any inline tracks
+    #[serde(default)]
+    pub network: Vec<YamlTrack>,
+    #[serde(default)]
+    pub rolling_stock_file: Option<String>, // relative to the scenario file
+    #[serde(default)]
+    pub rolling_stock: Vec<RollingStock>, // after loading, the built in catalogue then the file then these, later classes overriding earlier
+    pub trains: Vec<YamlTrain>,
+}
+
+pub fn load_scenario(path: &str) -> Result<YamlScenario, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let mut scenario: YamlScenario = serde_yaml::from_str(&contents).map_err(|e| format!("could not parse {path}: {e}"))?;
+
+    if let Some(network_file) = &scenario.network_file {
+        let network_path = Path::new(path).parent().unwrap_or(Path::new("")).join(network_file);
+        scenario.network.extend(load_tracks(&network_path.to_string_lossy())?);
+    }
+
+    let mut rolling_stock = parse_rolling_stock(ROLLING_STOCK)?;
+    if let Some(rolling_stock_file) = &scenario.rolling_stock_file {
+        let rolling_stock_path = Path::new(path).parent().unwrap_or(Path::new("")).join(rolling_stock_file);
+        rolling_stock.extend(load_rolling_stock(&rolling_stock_path.to_string_lossy())?);
+    }
+    rolling_stock.append(&mut scenario.rolling_stock);
+    scenario.rolling_stock = rolling_stock;
+
+    Ok(scenario)
+}
+
+pub fn load_rolling_stock(path: &str) -> Result<Vec<RollingStock>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    parse_rolling_stock(&contents).map_err(|e| format!("could not parse {path}: {e}"))
+}
+
+pub fn parse_rolling_stock(contents: &str) -> Result<Vec<RollingStock>, String> {
+    serde_yaml::from_str(contents).map_err(|e| e.to_string())
+}
+
+// the last listing of a class wins, so a scenario can adjust a built in class
+pub fn find_class<'a>(rolling_stock: &'a [RollingStock], class: &str) -> Option<&'a RollingStock> {
+    rolling_stock.iter().rev().find(|rolling_stock| rolling_stock.class == class)
+}
+
+pub fn load_tracks(path: &str) -> Result<Vec<YamlTrack>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    parse_tracks(&contents).map_err(|e| format!("could not parse {path}: {e}"))
+}
+
+pub fn parse_tracks(contents: &str) -> Result<Vec<YamlTrack>, String> {
+    serde_yaml::from_str(contents).map_err(|e| e.to_string())
+}
+
+// each track becomes a node, and every edge INTO that node carries the track's block
+// (the block is shared between all incoming edges so junctions see the same signal)
+pub fn build_network(tracks: &[YamlTrack]) -> Result<Network, String> {
+    let mut blocks = HashMap::<BlockId, Arc<Mutex<Block>>>::new();
+
+    for track in tracks {
+        if track.name == DEAD_END.as_str() {
+            return Err(format!("track name {DEAD_END} is reserved"));
+        }
+
+        let mut block = if track.platforms.is_empty() {
+            Block::new_track(track.length as u32, track.limit, Signal::new())
+        }
+        else {
+            Block::new_station(track.length as u32, track.limit, Vec::new())
+        };
+        for length in &track.platforms {
+            if *length > track.length {
+                return Err(format!("track {} has a platform longer than itself", track.name));
+            }
+            block.add_platform(Platform::new(Signal::new(), *length as u32));
+        }
+        block.reverse = if track.reverse == DEAD_END.as_str() { None } else { Some(BlockId::new(&track.reverse)) };
+        block.profile = profile(track, tracks)?;
+
+        if blocks.insert(BlockId::new(&track.name), Arc::new(Mutex::new(block))).is_some() {
+            return Err(format!("duplicate track {}", track.name));
+        }
+    }
+
+    let mut network = Network::new();
+
+    for track in tracks {
+        let name = BlockId::new(&track.name);
+        network.add_node(name);
+
+        if track.reverse != DEAD_END.as_str() && !blocks.contains_key(&BlockId::new(&track.reverse)) {
+            return Err(format!("track {} has unknown reverse {}", track.name, track.reverse));
+        }
+
+        for next_track in &track.next_tracks {
+            if next_track == DEAD_END.as_str() {
+                continue;
+            }
+            let next_name = BlockId::new(next_track);
+            match blocks.get(&next_name) {
+                Some(block) => {
+                    network.add_edge(name, next_name, block.clone());
+                },
+                None => {
+                    return Err(format!("track {} has unknown next track {}", track.name, next_track));
+                },
+            }
+        }
+    }
+
+    // tracks nothing leads into are entered from the boundary
+    for track in tracks {
+        let name = BlockId::new(&track.name);
+        if network.neighbors_directed(name, petgraph::Direction::Incoming).next().is_none() {
+            network.add_edge(DEAD_END, name, blocks[&name].clone());
+        }
+    }
+
+    // a track that diverges or converges does so through a set of points, the first listed way is normal
+    for track in tracks {
+        let name = BlockId::new(&track.name);
+        let next_tracks: Vec<BlockId> = network.neighbors_directed(name, petgraph::Direction::Outgoing).collect();
+        let prev_tracks: Vec<BlockId> = network.neighbors_directed(name, petgraph::Direction::Incoming).collect();
+        let mut block = blocks[&name].lock().unwrap();
+
+        match next_tracks.as_slice() {
+            [_] | [] => (),
+            [normal, reverse] => block.points = Some(Points::new(*normal, *reverse)),
+            _ => return Err(format!("track {name} diverges more than two ways")),
+        }
+
+        match prev_tracks.as_slice() {
+            [_] | [] => (),
+            [normal, reverse] => block.trailing_points = Some(Points::new(*normal, *reverse)),
+            _ => return Err(format!("track {name} converges more than two ways")),
+        }
+    }
+
+    Ok(network)
+}
+
+fn profile(track: &YamlTrack, tracks: &[YamlTrack]) -> Result<Vec<Surface>, String> {
+    let starts: Vec<f32> = track.profile.iter().map(|surface| surface.start).collect();
+    if starts.windows(2).any(|pair| pair[0] >= pair[1]) || starts.iter().any(|start| *start < 0.0 || *start >= track.length) {
+        return Err(format!("track {} has a profile out of order or beyond its ends", track.name));
+    }
+    if track.profile.iter().any(|surface| surface.radius.is_some_and(|radius| radius <= 30.0)) {
+        return Err(format!("track {} has a curve too tight to run round", track.name));
+    }
+
+    match tracks.iter().find(|reverse| reverse.name == track.reverse) {
+        Some(reverse) if track.profile.is_empty() && !reverse.profile.is_empty() => Ok(reverse_profile(&reverse.profile, track.length)),
+        _ => Ok(track.profile.clone()),
+    }
+}
+
+// the scenario most tests run against, a single line with a passing loop in the middle, and its network
+#[cfg(test)]
+pub fn passing_loop() -> (YamlScenario, Network) {
+    let scenario = load_scenario(concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios/passing_loop.yaml")).unwrap();
+    let network = build_network(&scenario.network).unwrap();
+    (scenario, network)
+}
+
+#[test]
+fn test_build_network() {
+    let tracks = parse_tracks(include_str!("../../tracks.yaml")).unwrap();
+    let network = build_network(&tracks).unwrap();
+
+    assert!(network.contains_edge("1".into(), "2".into()));
+    assert!(network.contains_edge("2".into(), "3".into()));
+    assert!(network.contains_edge(DEAD_END, "1".into()));
+    assert!(network.contains_edge(DEAD_END, "4".into()));
+    assert!(network.neighbors_directed("3".into(), petgraph::Direction::Outgoing).next().is_none());
+    assert_eq!(network.edge_weight("1".into(), "2".into()).unwrap().lock().unwrap().length, 700);
+    assert_eq!(network.edge_weight("1".into(), "2".into()).unwrap().lock().unwrap().reverse, None);
+    assert_eq!(network.edge_weight("2".into(), "3".into()).unwrap().lock().unwrap().reverse, Some("4".into()));
+}
+
+#[test]
+fn test_load_scenario() {
+    let scenario = load_scenario(concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios/default.yaml")).unwrap();
+
+    assert_eq!(scenario.network.len(), 6);
+    assert_eq!(scenario.trains.len(), 2);
+    assert_eq!(scenario.trains[0].timetable[0], YamlStop { location: "3".to_string(), platform: 1, time: 2000, arrival: None, dwell: 30 });
+    assert_eq!(scenario.simulation, YamlParameters::default());
+    assert_eq!(find_class(&scenario.rolling_stock, "802").unwrap().length, 130.0);
+}
+
+#[test]
+fn test_rolling_stock() {
+    let mut rolling_stock = parse_rolling_stock(ROLLING_STOCK).unwrap();
+    for class in ["800", "802", "390", "66", "70", "150", "158"] {
+        assert!(find_class(&rolling_stock, class).is_some(), "class {class} missing");
+    }
+
+    rolling_stock.extend(parse_rolling_stock("- {class: \"802\", mass: 1.0, length: 1.0, power: 1.0, max_speed: 1.0, max_tractive_effort: 1.0, service_brake: 1.0, emergency_brake: 1.0, davis: {a: 0.0, b: 0.0, c: 0.0}}").unwrap());
+    assert_eq!(find_class(&rolling_stock, "802").unwrap().mass, 1.0);
+
+    // an effort curve out of speed order is refused when loaded, not read off wrongly later
+    assert!(parse_rolling_stock("- {class: \"802\", mass: 1.0, length: 1.0, power: 1.0, max_speed: 1.0, max_tractive_effort: 1.0, service_brake: 1.0, emergency_brake: 1.0, tractive_effort: [[10.0, 1.0], [0.0, 1.0]], davis: {a: 0.0, b: 0.0, c: 0.0}}").is_err());
+    assert!(parse_rolling_stock("- {class: \"802\", mass: 1.0, length: 1.0, power: 1.0, max_speed: 1.0, max_tractive_effort: 1.0, service_brake: 1.0, emergency_brake: 1.0, braking_effort: [], davis: {a: 0.0, b: 0.0, c: 0.0}}").is_err());
+}
+
+#[test]
+fn test_points() {
+    let (_, network) = passing_loop();
+
+    let points = network.edge_weight("W".into(), "E1".into()).unwrap().lock().unwrap().points.unwrap();
+    assert_eq!((points.normal, points.reverse), ("LE".into(), "ME".into()));
+
+    let trailing_points = network.edge_weight("LE".into(), "E2".into()).unwrap().lock().unwrap().trailing_points.unwrap();
+    assert_eq!((trailing_points.normal, trailing_points.reverse), ("LE".into(), "ME".into()));
+}
+
+#[test]
+fn test_profile() {
+    let (_, network) = passing_loop();
+
+    // W1 takes the profile of E1 the other way round
+    let east = network.edge_weight("W".into(), "E1".into()).unwrap().lock().unwrap().profile.clone();
+    let west = network.edge_weight("MW".into(), "W1".into()).unwrap().lock().unwrap().profile.clone();
+    assert_eq!(west, crate::utils::surface::reverse_profile(&east, 2000.0));
+
+    let tracks = parse_tracks("- {name: \"1\", length: 100.0, limit: 30.0, reverse: \"-1\", next_tracks: [], profile: [{start: 50.0}, {start: 20.0}]}").unwrap();
+    assert!(build_network(&tracks).is_err());
+}
+
+#[test]
+fn test_unknown_track() {
+    let tracks = parse_tracks("- {name: \"1\", length: 100.0, limit: 30.0, reverse: \"-1\", next_tracks: [\"2\"]}").unwrap();
+    assert!(build_network(&tracks).is_err());
+}
@@ -0,0 +1,77 @@
+use crate::{
+    infrastructure::block::Block,
+    utils::{id::BlockId, io::{DEAD_END, Network}}
+};
+use petgraph::{algo::astar, graphmap::DiGraphMap, Direction::Outgoing};
+use serde::Deserialize;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteMetric {
+    Shortest,
+    #[default]
+    Fastest,
+}
+
+// cost of every possible move between blocks, including changing ends at a terminus
+fn routing_graph(network: &Network, metric: RouteMetric) -> DiGraphMap::<BlockId, f32> {
+    let mut graph = DiGraphMap::<BlockId, f32>::new();
+
+    let cost = |block: &Block| match metric {
+        RouteMetric::Shortest => block.length as f32,
+        RouteMetric::Fastest => block.length as f32 / block.limit,
+    };
+
+    for (block_id, next_block_id, next_block) in network.all_edges() {
+        if block_id != DEAD_END {
+            graph.add_edge(block_id, next_block_id, cost(&next_block.lock().unwrap()));
+        }
+    }
+
+    for block_id in network.nodes() {
+        if block_id == DEAD_END || network.neighbors_directed(block_id, Outgoing).next().is_some() {
+            continue;
+        }
+        if let Some((_, _, block)) = network.edges_directed(block_id, petgraph::Direction::Incoming).next() {
+            if let Some(reverse_block_id) = block.lock().unwrap().reverse {
+                let reverse_block = network.edges_directed(reverse_block_id, petgraph::Direction::Incoming).next().unwrap().2;
+                graph.add_edge(block_id, reverse_block_id, cost(&reverse_block.lock().unwrap()));
+            }
+        }
+    }
+
+    graph
+}
+
+// blocks a train passes through from its start to each location in turn, including both ends
+pub fn plan_route(network: &Network, start: BlockId, locations: &[BlockId], metric: RouteMetric) -> Result<Vec<BlockId>, String> {
+    let graph = routing_graph(network, metric);
+    let mut route = vec![start];
+
+    for location in locations {
+        let from = *route.last().unwrap();
+        if !graph.contains_node(*location) {
+            return Err(format!("unknown location {location}"));
+        }
+        let (_, path) = astar(&graph, from, |block_id| block_id == *location, |edge| *edge.2, |_| 0.0)
+            .ok_or(format!("no route from {from} to {location}"))?;
+        route.extend(path.into_iter().skip(1));
+    }
+
+    Ok(route)
+}
+
+#[test]
+fn test_plan_route() {
+    let (_, network) = crate::utils::io::passing_loop();
+
+    // loop and main line are equally quick, so either may be picked
+    let route = plan_route(&network, "W".into(), &["E".into(), "Wr".into()], RouteMetric::Fastest).unwrap();
+    assert_eq!(route.len(), 10);
+    assert_eq!([route[0], route[1], route[3], route[4], route[5], route[6]], ["W", "E1", "E2", "E", "Er", "W2"]);
+    assert!(route[2] == "LE" || route[2] == "ME");
+    assert_eq!(route[9], "Wr");
+
+    assert!(plan_route(&network, "W".into(), &["X".into()], RouteMetric::Shortest).is_err());
+    assert_eq!(plan_route(&network, "W".into(), &[], RouteMetric::Shortest).unwrap(), vec!["W"]);
+}
@@ -0,0 +1,171 @@
+use crate::utils::{schematic::Schematic, visualiser::{Frame, Visualiser, keys}};
+use console::Key;
+use std::{fs::{self, File}, io::{BufWriter, Write}, thread, time::{Duration, Instant}};
+
+const SEEK: f32 = 10.0; // simulated seconds skipped by a short seek
+const LONG_SEEK: f32 = 60.0;
+const MAX_SPEED: f32 = 1024.0;
+
+// the layout of the network and then every frame the visualiser would draw, one JSON object per line
+pub struct Recording {
+    writer: BufWriter<File>,
+}
+
+impl Recording {
+    pub fn create(path: &str, schematic: &Schematic) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("could not create {path}: {e}"))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, schematic).unwrap();
+        writer.write_all(b"\n").map_err(|e| format!("could not write {path}: {e}"))?;
+        Ok(Recording { writer })
+    }
+
+    pub fn write(&mut self, frame: &Frame) {
+        serde_json::to_writer(&mut self.writer, frame).unwrap();
+        self.writer.write_all(b"\n").unwrap();
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().unwrap();
+    }
+}
+
+pub fn load_recording(path: &str) -> Result<(Schematic, Vec<Frame>), String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let mut lines = contents.lines().enumerate();
+    let schematic = match lines.next() {
+        Some((_, schematic)) => serde_json::from_str(schematic).map_err(|e| format!("{path} line 1: {e}"))?,
+        None => return Err(format!("{path} is empty")),
+    };
+    let frames: Vec<Frame> = lines
+        .map(|(line, frame)| serde_json::from_str(frame).map_err(|e| format!("{path} line {}: {e}", line + 1)))
+        .collect::<Result<_, _>>()?;
+
+    if frames.is_empty() {
+        return Err(format!("{path} has no frames"));
+    }
+    Ok((schematic, frames))
+}
+
+// where playback has got to in simulated time, and how fast it is moving
+struct Player {
+    frames: Vec<Frame>,
+    time: f32,
+    speed: f32,
+    paused: bool,
+}
+
+impl Player {
+    fn new(frames: Vec<Frame>, speed: f32) -> Self {
+        let time = frames[0].time;
+        Player { frames, time, speed, paused: false }
+    }
+
+    fn start(&self) -> f32 {
+        self.frames[0].time
+    }
+
+    fn end(&self) -> f32 {
+        self.frames.last().unwrap().time
+    }
+
+    // the last frame at or before the playback time
+    fn index(&self) -> usize {
+        self.frames.partition_point(|frame| frame.time <= self.time).saturating_sub(1)
+    }
+
+    fn seek(&mut self, by: f32) {
+        self.time = (self.time + by).clamp(self.start(), self.end());
+    }
+
+    fn advance(&mut self, elapsed: Duration) {
+        if !self.paused {
+            self.seek(elapsed.as_secs_f32() * self.speed);
+        }
+        // stop at the end so it can be scrubbed back from
+        if self.time >= self.end() {
+            self.paused = true;
+        }
+    }
+
+    fn control(&mut self, key: Key) {
+        match key {
+            Key::Char(' ') => {
+                // playing again from the end starts over
+                if self.paused && self.time >= self.end() {
+                    self.time = self.start();
+                }
+                self.paused = !self.paused;
+            },
+            Key::ArrowLeft => self.seek(-SEEK),
+            Key::ArrowRight => self.seek(SEEK),
+            Key::ArrowDown => self.seek(-LONG_SEEK),
+            Key::ArrowUp => self.seek(LONG_SEEK),
+            Key::Char('r') | Key::Home => self.time = self.start(),
+            Key::End => self.time = self.end(),
+            Key::Char('+') | Key::Char('=') => self.speed = (self.speed * 2.0).min(MAX_SPEED),
+            Key::Char('-') => self.speed = (self.speed / 2.0).max(1.0 / MAX_SPEED),
+            _ => (),
+        }
+    }
+}
+
+// play a recording back through the visualiser until quit
+pub fn replay(path: &str, speed: f32) -> Result<(), String> {
+    let (schematic, frames) = load_recording(path)?;
+    let mut player = Player::new(frames, speed);
+    let visualiser = Visualiser::new(schematic);
+
+    let rx = keys();
+
+    let mut last = Instant::now();
+    let mut drawn = None;
+
+    loop {
+        for key in rx.try_iter() {
+            match key {
+                Key::Char('q') | Key::Escape => return Ok(()),
+                key => player.control(key),
+            }
+        }
+
+        let now = Instant::now();
+        player.advance(now - last);
+        last = now;
+
+        let shown = (player.index(), player.speed, player.paused);
+        if drawn != Some(shown) {
+            visualiser.update(&player.frames[shown.0]);
+            visualiser.controls(player.end(), player.speed, player.paused);
+            drawn = Some(shown);
+        }
+
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn test_player() {
+    let frames = (0..=100).map(|second| Frame { time: second as f32, trains: Vec::new(), signals: Vec::new(), positions: Vec::new() }).collect();
+    let mut player = Player::new(frames, 4.0);
+
+    player.advance(Duration::from_secs(5));
+    assert_eq!(player.index(), 20);
+
+    player.control(Key::ArrowLeft);
+    player.control(Key::Char(' '));
+    player.advance(Duration::from_secs(5));
+    assert_eq!(player.index(), 10); // paused
+
+    player.control(Key::ArrowDown);
+    assert_eq!(player.index(), 0);
+    player.control(Key::Char(' '));
+    player.control(Key::End);
+    player.advance(Duration::from_secs(1));
+    assert!(player.paused);
+    assert_eq!(player.index(), 100);
+
+    player.control(Key::Char(' ')); // from the top again
+    assert!(!player.paused);
+    assert_eq!(player.index(), 0);
+}
@@ -0,0 +1,343 @@
+use crate::{
+    infrastructure::{block::BlockType, signal::SignalColour},
+    utils::{id::{BlockId, SignalId}, io::{DEAD_END, Network}, visualiser::Frame}
+};
+use petgraph::Direction::{Incoming, Outgoing};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+const MIN_WIDTH: usize = 12; // characters across a block, room for a train id between its signals
+const SIGNAL: char = '●';
+
+// ends of a line joining at a character, indexing BOX
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+const BOX: [char; 16] = [' ', '│', '│', '│', '─', '┘', '┐', '┤', '─', '└', '┌', '├', '─', '┴', '┬', '┼'];
+
+// a piece of track drawn once however many ways it is worked, the block running left to right is
+// signalled at its right end and the block running right to left at its left end
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Track {
+    right: Option<BlockId>,
+    left: Option<BlockId>,
+    platforms: usize, // drawn one above the other, none for plain track
+    row: usize,
+    start: usize, // first and last columns it is drawn across
+    end: usize,
+}
+
+impl Track {
+    fn lines(&self) -> usize {
+        self.platforms.max(1)
+    }
+
+    fn signal(&self, block_id: Option<BlockId>, line: usize) -> Option<SignalId> {
+        block_id.map(|block_id| SignalId::new(block_id, (self.platforms > 0).then_some(line + 1)))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    Open,
+    Done,
+}
+
+// where every block is drawn in the terminal, laid out once from the network so recordings can be drawn without it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schematic {
+    tracks: Vec<Track>,
+    links: Vec<(usize, usize)>, // tracks joined end to end, left then right
+    loops: Vec<(BlockId, BlockId)>, // joins that would close a loop, which are named rather than drawn
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ink {
+    Track,
+    Label,
+    Signal(SignalColour),
+    Train,
+}
+
+impl Schematic {
+    pub fn new(network: &Network) -> Self {
+        let block = |block_id: BlockId| network.edges_directed(block_id, Incoming).next().unwrap().2.clone();
+        let platforms = |block_id: Option<BlockId>| match block_id.map(|block_id| block(block_id).lock().unwrap().block_type.clone()) {
+            Some(BlockType::Station { platforms }) => platforms.len(),
+            _ => 0,
+        };
+        let sorted = |mut block_ids: Vec<BlockId>| {
+            block_ids.sort();
+            block_ids
+        };
+
+        // lines are laid out left to right from where trains enter them, taken in the order the tracks are listed
+        let mut starts: Vec<BlockId> = network.nodes().filter(|block_id| *block_id != DEAD_END).collect();
+        starts.sort_by_key(|block_id| !network.contains_edge(DEAD_END, *block_id));
+
+        let mut tracks = Vec::new();
+        let mut parents = Vec::new(); // the track each was found from
+        let mut track_of = HashMap::new();
+        let mut rightward = HashSet::new();
+
+        for start in starts {
+            let mut queue = VecDeque::from([(start, true, None)]);
+            while let Some((block_id, right, parent)) = queue.pop_front() {
+                if track_of.contains_key(&block_id) {
+                    continue;
+                }
+                let index = tracks.len();
+                let reverse = block(block_id).lock().unwrap().reverse.filter(|reverse| *reverse != block_id && !track_of.contains_key(reverse));
+                let (right_block, left_block) = if right { (Some(block_id), reverse) } else { (reverse, Some(block_id)) };
+                tracks.push(Track { right: right_block, left: left_block, platforms: platforms(right_block).max(platforms(left_block)), row: 0, start: 0, end: 0 });
+                parents.push(parent);
+
+                // blocks either side of a block run the same way it does
+                for (block_id, right) in [(Some(block_id), right), (reverse, !right)] {
+                    let Some(block_id) = block_id else { continue };
+                    track_of.insert(block_id, index);
+                    if right {
+                        rightward.insert(block_id);
+                    }
+                    let next = sorted(network.neighbors_directed(block_id, Outgoing).collect());
+                    let prev = sorted(network.neighbors_directed(block_id, Incoming).filter(|id| *id != DEAD_END).collect());
+                    queue.extend(next.into_iter().chain(prev).map(|id| (id, right, Some(index))));
+                }
+            }
+        }
+
+        let mut joins = BTreeMap::new();
+        for (block_id, next_block_id, _) in network.all_edges() {
+            if block_id == DEAD_END || track_of[&block_id] == track_of[&next_block_id] {
+                continue;
+            }
+            let (from, to) = (track_of[&block_id], track_of[&next_block_id]);
+            let link = if rightward.contains(&block_id) { (from, to) } else { (to, from) };
+            joins.entry(link).or_insert((block_id, next_block_id));
+        }
+
+        // anything leading back round to a track still being followed closes a loop
+        let mut right_of = vec![Vec::new(); tracks.len()];
+        for (left, right) in joins.keys() {
+            right_of[*left].push(*right);
+        }
+        let mut visits = vec![None; tracks.len()];
+        let mut finished = Vec::new();
+        let mut back = HashSet::new();
+        for index in 0..tracks.len() {
+            if visits[index].is_none() {
+                visit(index, &right_of, &mut visits, &mut finished, &mut back);
+            }
+        }
+        let links: Vec<(usize, usize)> = joins.keys().filter(|link| !back.contains(*link)).copied().collect();
+        let loops = joins.iter().filter(|(link, _)| back.contains(*link)).map(|(_, join)| *join).collect();
+
+        // each track as far left as whatever joins it from the left allows, stretched to meet whatever it joins on the right
+        for index in finished.into_iter().rev() {
+            for (_, right) in links.iter().filter(|(left, _)| *left == index) {
+                tracks[*right].start = tracks[*right].start.max(tracks[index].start + 1);
+            }
+        }
+        for index in 0..tracks.len() {
+            let next_start = links.iter().filter(|(left, _)| *left == index).map(|(_, right)| tracks[*right].start).min();
+            tracks[index].end = next_start.map_or(tracks[index].start, |start| (start - 1).max(tracks[index].start));
+        }
+
+        // a track keeps to the row of the track it was found from if it can, otherwise it drops below,
+        // keeping clear the way its links come in from further left
+        let mut taken = HashSet::new();
+        for index in 0..tracks.len() {
+            let reach = links.iter().filter(|(_, right)| *right == index).map(|(left, _)| tracks[*left].end + 1).min().unwrap_or(usize::MAX).min(tracks[index].start);
+            let mut row = match parents[index] {
+                Some(parent) => tracks[parent].row,
+                None => taken.iter().map(|(row, _)| row + 2).max().unwrap_or(0), // a line of its own, below the rest
+            };
+            let (lines, end) = (tracks[index].lines(), tracks[index].end);
+            let cells = |row: usize| (row..row + lines).flat_map(move |row| (reach..=end).map(move |column| (row, column)));
+            while cells(row).any(|cell| taken.contains(&cell)) {
+                row += 1;
+            }
+            taken.extend(cells(row));
+            tracks[index].row = row;
+        }
+
+        Schematic { tracks, links, loops }
+    }
+
+    // the network as lines of characters, with every signal showing its aspect and each train where its front is
+    pub fn draw(&self, frame: &Frame) -> Vec<Vec<(char, Ink)>> {
+        let columns = self.tracks.iter().map(|track| track.end + 1).max().unwrap_or(0);
+        let mut widths = vec![MIN_WIDTH; columns];
+        for track in self.tracks.iter().filter(|track| track.start == track.end) {
+            for line in 0..track.lines() {
+                let labels = [track.signal(track.left, line), track.signal(track.right, line)];
+                let width = labels.iter().flatten().map(|signal_id| signal_id.to_string().chars().count() + 1).sum::<usize>() + 2;
+                widths[track.start] = widths[track.start].max(width);
+            }
+        }
+        // a gap after each column for the links between them
+        let mut x = vec![0];
+        for width in &widths {
+            x.push(x.last().unwrap() + width + 1);
+        }
+        let gap_after = |column: usize| x[column + 1] - 1;
+        let span = |track: &Track| (x[track.start], x[track.end] + widths[track.end] - 1);
+        let lines = |track: &Track| (0..track.lines()).map(|line| 2 * (track.row + line)).collect::<Vec<_>>();
+
+        let height = self.tracks.iter().map(|track| 2 * (track.row + track.lines())).max().unwrap_or(0);
+        let mut canvas = Canvas::new(*x.last().unwrap(), height);
+
+        let colours: HashMap<SignalId, SignalColour> = frame.signals.iter().copied().collect();
+        for track in &self.tracks {
+            let (left_end, right_end) = span(track);
+            for (line, y) in lines(track).into_iter().enumerate() {
+                for column in left_end..=right_end {
+                    canvas.join(y, column, LEFT | RIGHT);
+                }
+                // each signal is named underneath it
+                if let Some(signal_id) = track.signal(track.left, line) {
+                    canvas.put(y, left_end, SIGNAL, Ink::Signal(colours.get(&signal_id).copied().unwrap_or(SignalColour::Red)));
+                    canvas.text(y + 1, left_end, &signal_id.to_string(), Ink::Label);
+                }
+                if let Some(signal_id) = track.signal(track.right, line) {
+                    let label = signal_id.to_string();
+                    canvas.put(y, right_end, SIGNAL, Ink::Signal(colours.get(&signal_id).copied().unwrap_or(SignalColour::Red)));
+                    canvas.text(y + 1, right_end + 1 - label.chars().count(), &label, Ink::Label);
+                }
+            }
+        }
+
+        for (left, right) in &self.links {
+            let (from, to) = (&self.tracks[*left], &self.tracks[*right]);
+            let (a, b) = (gap_after(from.end), gap_after(to.start - 1));
+            let (from_lines, to_lines) = (lines(from), lines(to));
+            let first = to_lines[0];
+            for y in from_lines {
+                canvas.join(y, a, LEFT);
+                canvas.vertical(a, y, first);
+            }
+            // round to the first line of a track further right
+            if a < b {
+                canvas.join(first, a, RIGHT);
+                for column in a + 1..b {
+                    canvas.join(first, column, LEFT | RIGHT);
+                }
+                canvas.join(first, b, LEFT);
+            }
+            for y in to_lines {
+                canvas.vertical(b, first, y);
+                canvas.join(y, b, RIGHT);
+            }
+        }
+
+        for (train_id, signal_id, fraction) in &frame.positions {
+            let Some(track) = self.tracks.iter().find(|track| track.right == Some(signal_id.block_id) || track.left == Some(signal_id.block_id)) else {
+                continue;
+            };
+            let (left_end, right_end) = span(track);
+            let line = signal_id.platform.map_or(0, |platform| platform - 1).min(track.lines() - 1);
+            let inside = right_end - left_end - 1;
+            let name: String = train_id.as_str().chars().take(inside).collect();
+            let room = inside - name.chars().count();
+            let along = (fraction.clamp(0.0, 1.0) * room as f32).round() as usize;
+            let offset = if track.right == Some(signal_id.block_id) { along } else { room - along };
+            canvas.text(2 * (track.row + line), left_end + 1 + offset, &name, Ink::Train);
+        }
+
+        let mut drawn = canvas.into_lines();
+        if !self.loops.is_empty() {
+            let loops: Vec<String> = self.loops.iter().map(|(block_id, next_block_id)| format!("{block_id} → {next_block_id}")).collect();
+            drawn.push(format!("loops round: {}", loops.join(", ")).chars().map(|c| (c, Ink::Label)).collect());
+        }
+        drawn
+    }
+}
+
+// neighbouring characters drawn the same way, joined up
+pub fn runs(line: &[(char, Ink)]) -> Vec<(String, Ink)> {
+    line.chunk_by(|a, b| a.1 == b.1).map(|run| (run.iter().map(|(c, _)| c).collect(), run[0].1)).collect()
+}
+
+fn visit(index: usize, right_of: &[Vec<usize>], visits: &mut [Option<Visit>], finished: &mut Vec<usize>, back: &mut HashSet<(usize, usize)>) {
+    visits[index] = Some(Visit::Open);
+    for next in &right_of[index] {
+        match visits[*next] {
+            None => visit(*next, right_of, visits, finished, back),
+            Some(Visit::Open) => {
+                back.insert((index, *next));
+            },
+            Some(Visit::Done) => (),
+        }
+    }
+    visits[index] = Some(Visit::Done);
+    finished.push(index);
+}
+
+// the ends of the lines meeting at a character, and anything written over them
+type Cell = (u8, Option<(char, Ink)>);
+
+struct Canvas {
+    cells: Vec<Vec<Cell>>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Canvas { cells: vec![vec![(0, None); width]; height] }
+    }
+
+    fn join(&mut self, y: usize, x: usize, ends: u8) {
+        self.cells[y][x].0 |= ends;
+    }
+
+    fn vertical(&mut self, x: usize, from: usize, to: usize) {
+        for y in from.min(to)..from.max(to) {
+            self.join(y, x, DOWN);
+            self.join(y + 1, x, UP);
+        }
+    }
+
+    fn put(&mut self, y: usize, x: usize, c: char, ink: Ink) {
+        self.cells[y][x].1 = Some((c, ink));
+    }
+
+    fn text(&mut self, y: usize, x: usize, text: &str, ink: Ink) {
+        for (index, c) in text.chars().enumerate() {
+            if let Some(cell) = self.cells[y].get_mut(x + index) {
+                cell.1 = Some((c, ink));
+            }
+        }
+    }
+
+    fn into_lines(self) -> Vec<Vec<(char, Ink)>> {
+        self.cells.into_iter().map(|line| {
+            line.into_iter().map(|(ends, written)| written.unwrap_or((BOX[ends as usize], Ink::Track))).collect()
+        }).collect()
+    }
+}
+
+#[test]
+fn test_layout() {
+    let (_, network) = crate::utils::io::passing_loop();
+    let schematic = Schematic::new(&network);
+    let placed = |name: &str| schematic.tracks.iter().find(|track| track.right == Some(name.into())).map(|track| (track.row, track.start, track.left));
+
+    assert_eq!(placed("W"), Some((0, 0, Some("Wr".into()))));
+    assert_eq!(placed("E1"), Some((0, 1, Some("W1".into()))));
+    assert_eq!(placed("LE"), Some((0, 2, Some("LW".into()))));
+    assert_eq!(placed("ME"), Some((1, 2, Some("MW".into())))); // the other way round the loop, underneath
+    assert_eq!(placed("E"), Some((0, 4, Some("Er".into()))));
+
+    // the loop is drawn once both ways, and 1A01 just past halfway along W
+    let frame = Frame { time: 0.0, trains: Vec::new(), signals: Vec::new(), positions: vec![("1A01".into(), SignalId::new("W".into(), None), 0.6)] };
+    let lines: Vec<String> = schematic.draw(&frame).iter().map(|line| line.iter().map(|(c, _)| c).collect()).collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("●────1A01──●─●──────────●┬●"));
+    assert_eq!(lines[2].matches(SIGNAL).count(), 2);
+    assert!(lines[1].starts_with("Wr") && lines[1].contains(" W ") && lines[1].contains("│"));
+
+    let scenario = crate::utils::io::load_scenario(concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios/station.yaml")).unwrap();
+    let schematic = Schematic::new(&crate::utils::io::build_network(&scenario.network).unwrap());
+    assert_eq!(schematic.loops, vec![("C".into(), "A".into())]);
+}
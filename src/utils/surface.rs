@@ -1,19 +1,63 @@
 use crate::GRAVITY;
+use serde::{Deserialize, Serialize};
 
+// the lie of the track from `start` metres into a block until the next surface begins
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Surface {
-    rolling_coefficient: f32,
-    friction_coefficient: f32,
+    pub start: f32,
+    #[serde(default)]
+    pub gradient: f32, // rise over distance in the direction of travel, 0.01 is 1 in 100 up
+    #[serde(default)]
+    pub radius: Option<f32>, // of the curve in metres, straight if none
 }
 
 impl Surface {
-    pub fn new(rolling_coefficient: f32) -> Self {
-        Self {
-            rolling_coefficient,
-            friction_coefficient: 0.0,
+    // the part of the train's weight acting along the track, against it uphill and with it downhill
+    pub fn gradient_force(&self, mass: f32) -> f32 {
+        mass * GRAVITY * self.gradient
+    }
+
+    // extra resistance from forcing the wheelsets round a curve, rockl's formula for standard gauge
+    pub fn curve_force(&self, mass: f32) -> f32 {
+        match self.radius {
+            Some(radius) if radius >= 300.0 => mass * GRAVITY * 0.65 / (radius - 55.0),
+            Some(radius) => mass * GRAVITY * 0.5 / (radius - 30.0),
+            None => 0.0,
         }
     }
+}
+
+// the surface some way into a block, level and straight before the first one
+pub fn surface_at(profile: &[Surface], position: f32) -> Surface {
+    profile.iter().rev().find(|surface| surface.start <= position).copied().unwrap_or_default()
+}
+
+// the same track seen by a train running the other way along it
+pub fn reverse_profile(profile: &[Surface], length: f32) -> Vec<Surface> {
+    let mut reversed = Vec::new();
+    let mut end = length;
 
-    pub fn calculate_friction(&self, mass: f32) -> f32 {
-        mass * GRAVITY * self.rolling_coefficient
+    for surface in profile.iter().rev() {
+        reversed.push(Surface { start: length - end, gradient: -surface.gradient, radius: surface.radius });
+        end = surface.start;
     }
-}
\ No newline at end of file
+    if end > 0.0 {
+        reversed.push(Surface { start: length - end, ..Default::default() });
+    }
+
+    reversed
+}
+
+#[test]
+fn test_reverse_profile() {
+    let profile = [Surface { start: 200.0, gradient: 0.01, radius: None }, Surface { start: 600.0, gradient: -0.005, radius: Some(800.0) }];
+    let reversed = reverse_profile(&profile, 1000.0);
+
+    assert_eq!(reversed, vec![
+        Surface { start: 0.0, gradient: 0.005, radius: Some(800.0) },
+        Surface { start: 400.0, gradient: -0.01, radius: None },
+        Surface { start: 800.0, gradient: 0.0, radius: None },
+    ]);
+    assert_eq!(surface_at(&profile, 100.0), Surface::default());
+    assert_eq!(surface_at(&reversed, 500.0).gradient, -0.01);
+}
@@ -0,0 +1,209 @@
+use crate::{
+    infrastructure::signal::SignalColour,
+    control::driver::Driver,
+    utils::{events::Event, id::{BlockId, SignalId, TrainId}, io::{DEAD_END, Network}}
+};
+use petgraph::Direction::Incoming;
+use std::{collections::BTreeMap, fmt::Write, fs, ops::Deref};
+
+const SAMPLE_INTERVAL: f32 = 1.0; // simulated seconds between points on a train's path
+const MAX_JUMP: f32 = 100.0; // metres, further than any train runs in one sample interval
+const WIDTH: f32 = 1200.0;
+const HEIGHT: f32 = 700.0;
+const MARGIN: f32 = 80.0;
+const TRAIN_COLOURS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#9467bd", "#8c564b", "#e377c2"];
+
+// time-distance diagram of a corridor, time along the bottom and distance up the side,
+// with each train's path, when every block was occupied and what its signal showed
+pub struct TrainGraph {
+    corridor: Vec<(BlockId, f32, f32)>, // block id, distance from the start of the corridor, length
+    reverse: BTreeMap<BlockId, usize>, // blocks running the other way over the corridor's track
+    paths: BTreeMap<TrainId, Vec<Vec<(f32, f32)>>>, // train id and each unbroken run of (time, distance) along the corridor
+    last_sample: BTreeMap<TrainId, (f32, BlockId)>,
+    occupancy: Vec<(usize, TrainId, f32, Option<f32>)>, // corridor index, train id, entered, left
+    aspects: Vec<(usize, SignalColour, f32)>, // corridor index, aspect, from
+    end: f32,
+}
+
+impl TrainGraph {
+    pub fn new(corridor: &[&str], network: &Network) -> Result<Self, String> {
+        let mut blocks = Vec::new();
+        let mut reverse = BTreeMap::new();
+        let mut distance = 0.0;
+
+        for (index, name) in corridor.iter().enumerate() {
+            let block_id = network.nodes().find(|block_id| block_id == name && *block_id != DEAD_END).ok_or(format!("unknown corridor block {name}"))?;
+            let block = network.edges_directed(block_id, Incoming).next().unwrap().2.lock().unwrap();
+            blocks.push((block_id, distance, block.length as f32));
+            if let Some(reverse_block_id) = block.reverse {
+                reverse.insert(reverse_block_id, index);
+            }
+            distance += block.length as f32;
+        }
+
+        if blocks.is_empty() {
+            return Err("corridor has no blocks".to_string());
+        }
+
+        Ok(TrainGraph {
+            corridor: blocks,
+            reverse,
+            paths: BTreeMap::new(),
+            last_sample: BTreeMap::new(),
+            occupancy: Vec::new(),
+            aspects: Vec::new(),
+            end: 0.0,
+        })
+    }
+
+    fn index(&self, block_id: BlockId) -> Option<usize> {
+        self.corridor.iter().position(|(id, _, _)| *id == block_id)
+    }
+
+    // distance along the corridor of a position in a block, if the block covers any of it
+    fn distance(&self, block_id: BlockId, position: f32) -> Option<f32> {
+        if let Some(index) = self.index(block_id) {
+            let (_, start, length) = self.corridor[index];
+            return Some(start + position.clamp(0.0, length));
+        }
+        self.reverse.get(&block_id).map(|index| {
+            let (_, start, length) = self.corridor[*index];
+            start + length - position.clamp(0.0, length)
+        })
+    }
+
+    pub fn record(&mut self, time: f32, events: &[Event], drivers: &[impl Deref<Target = Driver>]) {
+        self.end = time;
+
+        for event in events {
+            match event {
+                Event::BlockEntered { train_id, block_id, .. } => {
+                    if let Some(index) = self.index(*block_id).or_else(|| self.reverse.get(block_id).copied()) {
+                        self.occupancy.push((index, *train_id, time, None));
+                    }
+                },
+                Event::BlockCleared { train_id, block_id } => {
+                    if let Some(index) = self.index(*block_id).or_else(|| self.reverse.get(block_id).copied()) {
+                        for occupation in self.occupancy.iter_mut().filter(|(other, occupant, _, left)| *other == index && occupant == train_id && left.is_none()) {
+                            occupation.3 = Some(time);
+                        }
+                    }
+                },
+                Event::SignalChanged { signal_id: SignalId { block_id, platform: None }, colour, .. } => {
+                    if let Some(index) = self.index(*block_id) {
+                        self.aspects.push((index, *colour, time));
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        for driver in drivers {
+            let (train_id, block_id) = driver.status();
+            let moved_block = self.last_sample.get(&train_id).is_none_or(|(_, last_block_id)| *last_block_id != block_id);
+            if !moved_block && self.last_sample.get(&train_id).is_some_and(|(last_time, _)| time - last_time < SAMPLE_INTERVAL) {
+                continue;
+            }
+            self.last_sample.insert(train_id, (time, block_id));
+
+            let distance = self.distance(block_id, driver.train.position);
+            let paths = self.paths.entry(train_id).or_default();
+            match distance {
+                Some(distance) => {
+                    // anything further than a train covers between samples means it came onto the corridor somewhere else
+                    let continues = paths.last().and_then(|path| path.last()).is_some_and(|(_, last)| (last - distance).abs() < MAX_JUMP);
+                    if !continues && paths.last().is_none_or(|path| !path.is_empty()) {
+                        paths.push(Vec::new());
+                    }
+                    paths.last_mut().unwrap().push((time, distance));
+                },
+                None => {
+                    if paths.last().is_none_or(|path| !path.is_empty()) {
+                        paths.push(Vec::new());
+                    }
+                },
+            }
+        }
+    }
+
+    pub fn write_svg(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.svg()).map_err(|e| format!("could not write {path}: {e}"))
+    }
+
+    fn svg(&self) -> String {
+        let (_, start, length) = self.corridor.last().unwrap();
+        let total = start + length;
+        let end = self.end.max(1.0);
+        let x = |time: f32| MARGIN + time / end * (WIDTH - 2.0 * MARGIN);
+        let y = |distance: f32| HEIGHT - MARGIN - distance / total * (HEIGHT - 2.0 * MARGIN);
+
+        let mut svg = String::new();
+        writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" font-family="monospace" font-size="11">"#).unwrap();
+        writeln!(svg, r#"<rect width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#).unwrap();
+
+        // block boundaries and names up the side
+        for (block_id, start, length) in &self.corridor {
+            writeln!(svg, r##"<line x1="{}" y1="{:.1}" x2="{}" y2="{:.1}" stroke="#ddd"/>"##, x(0.0), y(*start), x(end), y(*start)).unwrap();
+            writeln!(svg, r#"<text x="{}" y="{:.1}" text-anchor="end">{block_id}</text>"#, MARGIN - 8.0, y(start + length / 2.0) + 4.0).unwrap();
+        }
+
+        // time along the bottom, roughly ten divisions
+        let step = [10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0].into_iter().find(|step| end / step <= 10.0).unwrap_or(7200.0);
+        let mut time = 0.0;
+        while time <= end {
+            writeln!(svg, r##"<line x1="{:.1}" y1="{}" x2="{:.1}" y2="{}" stroke="#eee"/>"##, x(time), y(0.0), x(time), y(total)).unwrap();
+            writeln!(svg, r#"<text x="{:.1}" y="{}" text-anchor="middle">{time}s</text>"#, x(time), HEIGHT - MARGIN + 16.0).unwrap();
+            time += step;
+        }
+
+        for (index, train_id, entered, left) in &self.occupancy {
+            let (_, start, length) = self.corridor[*index];
+            let colour = self.train_colour(*train_id);
+            writeln!(svg, r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{colour}" fill-opacity="0.12"/>"#,
+                x(*entered), y(start + length), x(left.unwrap_or(end)) - x(*entered), y(start) - y(start + length)).unwrap();
+        }
+
+        // each block's signal is at its far end
+        for (position, (index, colour, from)) in self.aspects.iter().enumerate() {
+            let until = self.aspects[position + 1..].iter().find(|(other, _, _)| other == index).map_or(end, |(_, _, time)| *time);
+            let (_, start, length) = self.corridor[*index];
+            let fill = match colour {
+                SignalColour::Red => "#d00",
+                SignalColour::Yellow => "#ec0",
+                SignalColour::DoubleYellow => "#f80",
+                SignalColour::Green => "#0a0",
+            };
+            writeln!(svg, r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="3" fill="{fill}"/>"#, x(*from), y(start + length) - 1.5, x(until) - x(*from)).unwrap();
+        }
+
+        for (train_id, paths) in &self.paths {
+            let colour = self.train_colour(*train_id);
+            for path in paths.iter().filter(|path| path.len() > 1) {
+                let points: Vec<String> = path.iter().map(|(time, distance)| format!("{:.1},{:.1}", x(*time), y(*distance))).collect();
+                writeln!(svg, r#"<polyline points="{}" fill="none" stroke="{colour}" stroke-width="2"/>"#, points.join(" ")).unwrap();
+            }
+            if let Some((time, distance)) = paths.iter().flatten().next() {
+                writeln!(svg, r#"<text x="{:.1}" y="{:.1}" fill="{colour}">{train_id}</text>"#, x(*time) + 4.0, y(*distance) - 4.0).unwrap();
+            }
+        }
+
+        writeln!(svg, "</svg>").unwrap();
+        svg
+    }
+
+    fn train_colour(&self, train_id: TrainId) -> &'static str {
+        let index = self.paths.keys().position(|id| *id == train_id).unwrap_or(0);
+        TRAIN_COLOURS[index % TRAIN_COLOURS.len()]
+    }
+}
+
+#[test]
+fn test_distance() {
+    let (_, network) = crate::utils::io::passing_loop();
+    let graph = TrainGraph::new(&["W", "E1", "LE", "E2", "E"], &network).unwrap();
+
+    assert_eq!(graph.distance("E1".into(), 100.0), Some(600.0));
+    assert_eq!(graph.distance("W1".into(), 100.0), Some(2400.0)); // westbound over E1
+    assert_eq!(graph.distance("ME".into(), 100.0), None);
+    assert!(TrainGraph::new(&["W", "X"], &network).is_err());
+}
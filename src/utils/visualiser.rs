@@ -1,71 +1,149 @@
-use console::{Term, Style};
+use console::{Key, Term, Style};
 use crate::{
     infrastructure::{
-        signal::SignalColour, block::{Block, BlockType}
+        signal::SignalColour, block::BlockType
     },
-    control::driver::Driver
+    control::driver::Driver,
+    utils::{id::{SignalId, TrainId}, io::{DEAD_END, Network}, schematic::{Ink, Schematic, runs}}
 };
-use petgraph::graphmap::DiGraphMap;
-use std::sync::{Arc, Mutex};
+use petgraph::Direction::Incoming;
+use serde::{Deserialize, Serialize};
+use std::{ops::Deref, sync::mpsc::{channel, Receiver}, thread};
+
+// everything drawn at one moment, so a run can be recorded and played back
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub time: f32,
+    pub trains: Vec<String>, // status line of each train
+    pub signals: Vec<(SignalId, SignalColour)>,
+    pub positions: Vec<(TrainId, SignalId, f32)>, // front of each train, by the signal ahead of it and how far along its block it is
+}
+
+impl Frame {
+    pub fn capture(time: f32, drivers: &[impl Deref<Target = Driver>], network: &Network) -> Self {
+        let trains = drivers.iter().map(|driver| format!("{}{}", **driver, driver.dst)).collect();
+
+        let mut signals = Vec::new();
+        for block_id in network.nodes().filter(|block_id| *block_id != DEAD_END) {
+            match &network.edges_directed(block_id, Incoming).next().unwrap().2.lock().unwrap().block_type {
+                BlockType::Track { signal } => signals.push((SignalId::new(block_id, None), signal.colour)),
+                BlockType::Station { platforms } => {
+                    signals.extend(platforms.iter().enumerate().map(|(index, platform)| (SignalId::new(block_id, Some(index + 1)), platform.signal.colour)));
+                },
+            }
+        }
+
+        let positions = drivers.iter().map(|driver| {
+            let (train_id, block_id) = driver.status();
+            // a train in a station is drawn in the platform it was given
+            let platform = match &network.edges_directed(block_id, Incoming).next().unwrap().2.lock().unwrap().block_type {
+                BlockType::Track { .. } => None,
+                BlockType::Station { platforms } => platforms.iter().position(|platform| platform.occupant == Some(train_id)).map(|index| index + 1),
+            };
+            (train_id, SignalId::new(block_id, platform), driver.train.position / driver.train.block_length.max(1.0))
+        }).collect();
+
+        Frame { time, trains, signals, positions }
+    }
+}
+
+// key presses as they come, read on their own thread so drawing carries on in between
+pub fn keys() -> Receiver<Key> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let term = Term::stdout();
+        while let Ok(key) = term.read_key() {
+            if tx.send(key).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
 
 pub struct Visualiser {
     term: Term,
+    schematic: Schematic,
     r: Style,
     y: Style,
     dy: Style,
     g: Style,
+    t: Style,
+    l: Style,
 }
 
 impl Visualiser {
-    pub fn new() -> Self {
+    pub fn new(schematic: Schematic) -> Self {
         Self {
             term: Term::stdout(),
+            schematic,
 
             r: Style::new().red(),
             y: Style::new().yellow(),
             dy: Style::new().color256(172),
             g: Style::new().green(),
+            t: Style::new().cyan().bold(),
+            l: Style::new().dim(),
         }
     }
+
+    pub fn schematic(&self) -> &Schematic {
+        &self.schematic
+    }
     
-    pub fn update(&self, time_elapsed: f32, drivers: &Vec::<Driver>, network: &DiGraphMap::<&str, Arc<Mutex<Block>>>) {
+    pub fn update(&self, frame: &Frame) {
         self.term.clear_screen().unwrap();
 
-        self.term.write_line(&format!("Time: {time_elapsed:>9.2}s")).unwrap();
+        self.status(frame);
+        self.term.write_line("").unwrap();
 
-        let mut train_locations = Vec::<(&str, &str)>::new();
+        for line in self.schematic.draw(frame) {
+            let styled: String = runs(&line).into_iter().map(|(text, ink)| self.style(ink).apply_to(text).to_string()).collect();
+            self.term.write_line(styled.trim_end()).unwrap();
+        }
+    }
 
-        for driver in drivers {
-            self.term.write_line(&format!("{driver}{}", driver.dst)).unwrap();
-            train_locations.push(driver.status());
+    fn style(&self, ink: Ink) -> Style {
+        match ink {
+            Ink::Track => Style::new(),
+            Ink::Label => self.l.clone(),
+            Ink::Signal(SignalColour::Red) => self.r.clone(),
+            Ink::Signal(SignalColour::Yellow) => self.y.clone(),
+            Ink::Signal(SignalColour::DoubleYellow) => self.dy.clone(),
+            Ink::Signal(SignalColour::Green) => self.g.clone(),
+            Ink::Train => self.t.clone(),
         }
+    }
 
-        for block in network.all_edges() {
-            let mut colour: &Style = &self.r;
-            let mut train: &str = "";
-            
-            match &block.2.lock().unwrap().block_type {
-                BlockType::Track { signal } => {
-                    match signal.colour {
-                        SignalColour::Red => colour = &self.r,
-                        SignalColour::Yellow => colour = &self.y,
-                        SignalColour::DoubleYellow => colour = &self.dy,
-                        SignalColour::Green => colour = &self.g,
-                    }
-                },
-                BlockType::Station { platforms: _ } => {
-                    
-                },
-            }
+    pub fn status(&self, frame: &Frame) {
+        self.term.write_line(&format!("Time: {:>9.2}s", frame.time)).unwrap();
 
-            for loc in &train_locations {
-                if loc.1 == block.1 {
-                    train = loc.0;
-                }
-            }
+        for status in &frame.trains {
+            self.term.write_line(status).unwrap();
+        }
+    }
+
+    // where a replay has got to and how to drive it
+    pub fn controls(&self, end: f32, speed: f32, paused: bool) {
+        let state = if paused { "paused" } else { "playing" };
+        self.term.write_line(&format!("\nreplay of {end:.0}s, {state} at {speed}x | space pause | left/right seek 10s | up/down seek 60s | r rewind | +/- speed | q quit")).unwrap();
+    }
+
+    // the command being typed and what came of the last one
+    pub fn console(&self, line: &str, message: &str, speedup: f32, paused: bool) {
+        let state = if paused { "paused" } else { "running" };
+        self.term.write_line(&format!("\n{state} at {speedup}x | {message}")).unwrap();
+        self.term.write_str(&format!("> {line}")).unwrap();
+    }
+
+    // booked against actual times at every stop, once the run is over
+    pub fn timetable(&self, drivers: &[impl Deref<Target = Driver>]) {
+        self.term.write_line("").unwrap();
 
-            self.term.write_str(&format!("{:>7} {} ", train, colour.apply_to(block.1))).unwrap();
+        for driver in drivers {
+            for stop in &driver.timetable {
+                self.term.write_line(&format!("{:>8} | {stop}", driver.train.name)).unwrap();
+            }
         }
-        
     }
 }